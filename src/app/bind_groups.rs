@@ -1,29 +1,41 @@
+use crate::scene::Scene;
+
+// The compute stage binds ten storage buffers, two more than wgpu's defaults
+// allow, and textures can outgrow the default binding size.
+pub fn required_limits(adapter: &wgpu::Adapter) -> wgpu::Limits {
+    let supported = adapter.limits();
+    wgpu::Limits {
+        max_storage_buffers_per_shader_stage: 10,
+        max_storage_buffer_binding_size: supported.max_storage_buffer_binding_size,
+        max_buffer_size: supported.max_buffer_size,
+        ..wgpu::Limits::default()
+    }
+}
+
 pub struct BindGroups {
     pub scene_bind_group_layout: wgpu::BindGroupLayout,
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub compute_bind_group_layout: wgpu::BindGroupLayout,
     pub fragment_bind_group_layout: wgpu::BindGroupLayout,
-    pub texture_buffer_bind_group_layout: wgpu::BindGroupLayout,
+    pub accumulation_bind_group_layout: wgpu::BindGroupLayout,
     pub scene_bind_group: wgpu::BindGroup,
     pub camera_bind_group: wgpu::BindGroup,
     pub compute_bind_group: wgpu::BindGroup,
     pub fragment_bind_group: wgpu::BindGroup,
-    pub bind_group_a_read_b_write: wgpu::BindGroup,
-    pub bind_group_b_read_a_write: wgpu::BindGroup,
+    pub accumulation_bind_group: wgpu::BindGroup,
 }
 
 impl BindGroups {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         sampler: &wgpu::Sampler,
-        vertex_buffer: &wgpu::Buffer,
-        tri_buffer: &wgpu::Buffer,
-        material_buffer: &wgpu::Buffer,
+        scene: &Scene,
         camera_buffer: &wgpu::Buffer,
         frame_buffer: &Option<wgpu::Buffer>,
-        texture_buffer_a: &wgpu::Buffer,
-        texture_buffer_b: &wgpu::Buffer,
+        accumulation_buffer: &wgpu::Buffer,
         texture_view: &wgpu::TextureView,
+        display_buffer: &wgpu::Buffer,
     ) -> BindGroups {
         let scene_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -57,25 +69,70 @@ impl BindGroups {
                         min_binding_size: None,
                     },
                     count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 }],
             });
 
-        let scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Scene Bind Group"),
-            layout: &scene_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: material_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: vertex_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: tri_buffer.as_entire_binding(),
-            }],
-        });
+        let scene_bind_group = create_scene_bind_group(device, &scene_bind_group_layout, scene);
         
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -142,7 +199,7 @@ impl BindGroups {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                    resource: wgpu::BindingResource::TextureView(texture_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
@@ -151,63 +208,24 @@ impl BindGroups {
             ],
         });
 
-        let texture_buffer_bind_group_layout =
+        let accumulation_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Texture Buffer Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
+                label: Some("Accumulation Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
                     },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }
-                ],
+                    count: None,
+                }],
             });
 
-        let bind_group_a_read_b_write = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_buffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: texture_buffer_a.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: texture_buffer_b.as_entire_binding(),
-                },
-            ],
-            label: Some("BindGroup A->B"),
-        });
-        
-        let bind_group_b_read_a_write = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_buffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: texture_buffer_b.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: texture_buffer_a.as_entire_binding(),
-                },
-            ],
-            label: Some("BindGroup B->A"),
-        });
-            
+        let accumulation_bind_group =
+            create_accumulation_bind_group(device, &accumulation_bind_group_layout, accumulation_buffer);
+
         let fragment_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Fragment Bind Group Layout"),
@@ -228,6 +246,16 @@ impl BindGroups {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -237,11 +265,15 @@ impl BindGroups {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                    resource: wgpu::BindingResource::TextureView(texture_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: display_buffer.as_entire_binding(),
                 },
             ],
         });
@@ -251,13 +283,12 @@ impl BindGroups {
             camera_bind_group_layout,
             compute_bind_group_layout,
             fragment_bind_group_layout,
-            texture_buffer_bind_group_layout,
+            accumulation_bind_group_layout,
             scene_bind_group,
             camera_bind_group,
             compute_bind_group,
             fragment_bind_group,
-            bind_group_a_read_b_write,
-            bind_group_b_read_a_write,
+            accumulation_bind_group,
         }
     }
 
@@ -266,6 +297,7 @@ impl BindGroups {
         device: &wgpu::Device,
         sampler: &wgpu::Sampler,
         texture_view: &wgpu::TextureView,
+        display_buffer: &wgpu::Buffer,
     ) {
         self.fragment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Fragment Bind Group"),
@@ -273,11 +305,15 @@ impl BindGroups {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                    resource: wgpu::BindingResource::TextureView(texture_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: display_buffer.as_entire_binding(),
                 },
             ],
         });
@@ -299,7 +335,7 @@ impl BindGroups {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                    resource: wgpu::BindingResource::TextureView(texture_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
@@ -309,66 +345,70 @@ impl BindGroups {
         });
     }
 
-    pub fn rebuild_texture_buffer_bind_groups(
-        &mut self,
-        device: &wgpu::Device,
-        texture_buffer_a: &wgpu::Buffer,
-        texture_buffer_b: &wgpu::Buffer,
-
-    ) {
-        self.bind_group_a_read_b_write = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.texture_buffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: texture_buffer_a.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: texture_buffer_b.as_entire_binding(),
-                },
-            ],
-            label: Some("BindGroup A->B"),
-        });
-        
-        self.bind_group_b_read_a_write = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.texture_buffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: texture_buffer_b.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: texture_buffer_a.as_entire_binding(),
-                },
-            ],
-            label: Some("BindGroup B->A"),
-        });
+    pub fn rebuild_accumulation_bind_group(&mut self, device: &wgpu::Device, accumulation_buffer: &wgpu::Buffer) {
+        self.accumulation_bind_group =
+            create_accumulation_bind_group(device, &self.accumulation_bind_group_layout, accumulation_buffer);
     }
 
-    pub fn rebuild_scene_bind_group(
-        &mut self,
-        device: &wgpu::Device,
-        material_buffer: &wgpu::Buffer,
-        vertex_buffer: &wgpu::Buffer,
-        tri_buffer: &wgpu::Buffer,
-    ) {
-        self.scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Scene Bind Group"),
-            layout: &self.scene_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: material_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: vertex_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: tri_buffer.as_entire_binding(),
-            }],
-        });
+    pub fn rebuild_scene_bind_group(&mut self, device: &wgpu::Device, scene: &Scene) {
+        self.scene_bind_group = create_scene_bind_group(device, &self.scene_bind_group_layout, scene);
     }
-}
\ No newline at end of file
+}
+
+fn create_scene_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, scene: &Scene) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Scene Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: scene.material_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 1,
+            resource: scene.vertex_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 2,
+            resource: scene.tri_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 3,
+            resource: scene.bvh_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 4,
+            resource: scene.environment_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 5,
+            resource: scene.light_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 6,
+            resource: scene.environment_map_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 7,
+            resource: scene.vertex_attribute_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 8,
+            resource: scene.texture_buffer.as_entire_binding(),
+        }],
+    })
+}
+
+fn create_accumulation_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    accumulation_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Accumulation Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: accumulation_buffer.as_entire_binding(),
+        }],
+    })
+}
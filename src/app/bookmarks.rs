@@ -0,0 +1,186 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::output::output_dir;
+use crate::camera::CameraPose;
+
+#[derive(Debug)]
+pub enum BookmarkError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for BookmarkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookmarkError::Io(err) => write!(f, "failed to access bookmark file: {err}"),
+            BookmarkError::Parse(err) => write!(f, "invalid bookmark file: {err}"),
+            BookmarkError::Serialize(err) => write!(f, "failed to write bookmarks: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BookmarkError {}
+
+impl From<std::io::Error> for BookmarkError {
+    fn from(err: std::io::Error) -> Self {
+        BookmarkError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for BookmarkError {
+    fn from(err: toml::de::Error) -> Self {
+        BookmarkError::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for BookmarkError {
+    fn from(err: toml::ser::Error) -> Self {
+        BookmarkError::Serialize(err)
+    }
+}
+
+// A saved viewpoint. Slots 0 to 9 are bound to the number keys. Angles are in
+// degrees, including the vertical `fov`, as in scene files.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bookmark {
+    pub slot: u32,
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub pitch: f32,
+    pub yaw: f32,
+    pub fov: f32,
+}
+
+impl Bookmark {
+    pub fn from_pose(slot: u32, pose: &CameraPose) -> Bookmark {
+        Bookmark {
+            slot,
+            position: pose.position,
+            pitch: pose.pitch,
+            yaw: pose.yaw,
+            fov: pose.fov.to_degrees(),
+        }
+    }
+
+    // `base` with the bookmarked position, rotation and field of view; the lens
+    // and projection are left as they are.
+    pub fn pose(&self, base: &CameraPose) -> CameraPose {
+        CameraPose {
+            position: self.position,
+            pitch: self.pitch,
+            yaw: self.yaw,
+            fov: self.fov.to_radians(),
+            ..*base
+        }
+    }
+}
+
+// Bookmarks of one scene, stored as TOML in the same form as the scene file's
+// `[[bookmarks]]` tables.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bookmarks {
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn load(path: &Path) -> Result<Bookmarks, BookmarkError> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BookmarkError> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, slot: u32) -> Option<&Bookmark> {
+        self.bookmarks.iter().rev().find(|bookmark| bookmark.slot == slot)
+    }
+
+    // Replaces whatever was in the bookmark's slot, keeping slots in order.
+    pub fn set(&mut self, bookmark: Bookmark) {
+        self.bookmarks.retain(|existing| existing.slot != bookmark.slot);
+        let index = self.bookmarks.partition_point(|existing| existing.slot < bookmark.slot);
+        self.bookmarks.insert(index, bookmark);
+    }
+
+    // Takes every slot `other` has filled.
+    pub fn merge(&mut self, other: &Bookmarks) {
+        for bookmark in &other.bookmarks {
+            self.set(*bookmark);
+        }
+    }
+}
+
+// `<scene>.bookmarks.toml` next to the scene file, or `bookmarks.toml` where
+// screenshots go for the built-in test scene.
+pub fn bookmarks_path(scene: Option<&str>) -> PathBuf {
+    match scene {
+        Some(scene) => Path::new(scene).with_extension("bookmarks.toml"),
+        None => output_dir().join("bookmarks.toml"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Lens, Projection};
+
+    fn bookmark(slot: u32, x: f32) -> Bookmark {
+        Bookmark {
+            slot,
+            position: [x, -1.5, 0.25],
+            pitch: -10.0,
+            yaw: 95.5,
+            fov: 47.0,
+        }
+    }
+
+    #[test]
+    fn set_keeps_one_bookmark_per_slot_in_order() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.set(bookmark(3, 1.0));
+        bookmarks.set(bookmark(1, 2.0));
+        bookmarks.set(bookmark(3, 3.0));
+
+        let slots: Vec<u32> = bookmarks.bookmarks.iter().map(|bookmark| bookmark.slot).collect();
+        assert_eq!(slots, [1, 3]);
+        assert_eq!(bookmarks.get(3), Some(&bookmark(3, 3.0)));
+        assert_eq!(bookmarks.get(1), Some(&bookmark(1, 2.0)));
+        assert_eq!(bookmarks.get(0), None);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.set(bookmark(7, 0.1));
+        bookmarks.set(bookmark(0, -12.3));
+
+        let path = std::env::temp_dir().join(format!("bookmarks-{}.toml", std::process::id()));
+        bookmarks.save(&path).unwrap();
+        let loaded = Bookmarks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), bookmarks);
+    }
+
+    #[test]
+    fn poses_keep_the_lens_and_projection() {
+        let base = CameraPose {
+            lens: Lens { aperture: 0.1, ..Default::default() },
+            projection: Projection::Equirectangular,
+            ..CameraPose::looking_along([0.0; 3], [1.0, 0.0, 0.0], 1.0)
+        };
+        let pose = bookmark(2, 4.0).pose(&base);
+        assert_eq!(pose.position, [4.0, -1.5, 0.25]);
+        assert_eq!(pose.fov, 47.0f32.to_radians());
+        assert_eq!(pose.lens, base.lens);
+        assert_eq!(pose.projection, base.projection);
+    }
+}
@@ -0,0 +1,340 @@
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_TRIS: u32 = 4;
+const TRAVERSAL_COST: f32 = 1.0;
+// Deepest leaf the shader can reach: traversal holds one pending sibling per level
+// plus both children of the node it visits in a stack of `BVH_STACK_SIZE` (64).
+const MAX_DEPTH: u32 = 63;
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    aabb_min: [f32; 3],
+    // Index of the left child when `tri_count` is 0 (the right child follows it),
+    // otherwise the first triangle of the leaf.
+    left_or_first: u32,
+    aabb_max: [f32; 3],
+    tri_count: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Aabb {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Aabb {
+    fn empty() -> Self {
+        Aabb {
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+        }
+    }
+
+    fn grow_point(&mut self, point: [f32; 3]) {
+        self.min = std::array::from_fn(|axis| self.min[axis].min(point[axis]));
+        self.max = std::array::from_fn(|axis| self.max[axis].max(point[axis]));
+    }
+
+    fn grow(&mut self, other: &Aabb) {
+        self.min = std::array::from_fn(|axis| self.min[axis].min(other.min[axis]));
+        self.max = std::array::from_fn(|axis| self.max[axis].max(other.max[axis]));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min[0] > self.max[0]
+    }
+
+    fn area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ];
+        2.0 * (e[0] * e[1] + e[1] * e[2] + e[2] * e[0])
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bounds: Aabb,
+    count: u32,
+}
+
+struct BvhBuilder {
+    tri_bounds: Vec<Aabb>,
+    centroids: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    nodes: Vec<BvhNode>,
+    // Nodes left as leaves only because they reached `MAX_DEPTH`.
+    depth_limited: usize,
+}
+
+impl BvhBuilder {
+    fn node_bounds(&self, first: u32, count: u32) -> (Aabb, Aabb) {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &index in &self.indices[first as usize..(first + count) as usize] {
+            bounds.grow(&self.tri_bounds[index as usize]);
+            centroid_bounds.grow_point(self.centroids[index as usize]);
+        }
+        (bounds, centroid_bounds)
+    }
+
+    fn push_node(&mut self, first: u32, count: u32) -> (usize, Aabb) {
+        let (bounds, centroid_bounds) = self.node_bounds(first, count);
+        self.nodes.push(BvhNode {
+            aabb_min: bounds.min,
+            left_or_first: first,
+            aabb_max: bounds.max,
+            tri_count: count,
+        });
+        (self.nodes.len() - 1, centroid_bounds)
+    }
+
+    fn bin_index(&self, tri: u32, axis: usize, centroid_bounds: &Aabb) -> usize {
+        let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+        let offset = self.centroids[tri as usize][axis] - centroid_bounds.min[axis];
+        ((offset / extent * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1)
+    }
+
+    // Returns (axis, split bin, cost) of the cheapest binned SAH split.
+    fn find_split(&self, first: u32, count: u32, centroid_bounds: &Aabb) -> Option<(usize, usize, f32)> {
+        let mut best: Option<(usize, usize, f32)> = None;
+
+        for axis in 0..3 {
+            if centroid_bounds.max[axis] - centroid_bounds.min[axis] <= f32::EPSILON {
+                continue;
+            }
+
+            let mut bins = [Bin {
+                bounds: Aabb::empty(),
+                count: 0,
+            }; BIN_COUNT];
+
+            for &tri in &self.indices[first as usize..(first + count) as usize] {
+                let bin = &mut bins[self.bin_index(tri, axis, centroid_bounds)];
+                bin.bounds.grow(&self.tri_bounds[tri as usize]);
+                bin.count += 1;
+            }
+
+            let mut left_area = [0.0; BIN_COUNT - 1];
+            let mut left_count = [0; BIN_COUNT - 1];
+            let mut left_bounds = Aabb::empty();
+            let mut left_sum = 0;
+            for i in 0..BIN_COUNT - 1 {
+                left_bounds.grow(&bins[i].bounds);
+                left_sum += bins[i].count;
+                left_area[i] = left_bounds.area();
+                left_count[i] = left_sum;
+            }
+
+            let mut right_bounds = Aabb::empty();
+            let mut right_sum = 0;
+            for i in (1..BIN_COUNT).rev() {
+                right_bounds.grow(&bins[i].bounds);
+                right_sum += bins[i].count;
+                let cost = left_area[i - 1] * left_count[i - 1] as f32
+                    + right_bounds.area() * right_sum as f32;
+                if left_count[i - 1] > 0 && right_sum > 0 && best.is_none_or(|(_, _, c)| cost < c) {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+
+        best
+    }
+
+    fn subdivide(&mut self, node_index: usize, centroid_bounds: Aabb, depth: u32) {
+        let node = self.nodes[node_index];
+        let (first, count) = (node.left_or_first, node.tri_count);
+        if count <= MAX_LEAF_TRIS {
+            return;
+        }
+        if depth >= MAX_DEPTH {
+            self.depth_limited += 1;
+            return;
+        }
+
+        let parent_area = Aabb {
+            min: node.aabb_min,
+            max: node.aabb_max,
+        }
+        .area();
+        let Some((axis, split, cost)) = self.find_split(first, count, &centroid_bounds) else {
+            return;
+        };
+        if parent_area > 0.0 && TRAVERSAL_COST + cost / parent_area >= count as f32 {
+            return;
+        }
+
+        let mut i = first as usize;
+        let mut j = (first + count) as usize;
+        while i < j {
+            if self.bin_index(self.indices[i], axis, &centroid_bounds) < split {
+                i += 1;
+            } else {
+                j -= 1;
+                self.indices.swap(i, j);
+            }
+        }
+        let left_count = i as u32 - first;
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let (left_index, left_centroids) = self.push_node(first, left_count);
+        let (right_index, right_centroids) = self.push_node(first + left_count, count - left_count);
+        self.nodes[node_index].left_or_first = left_index as u32;
+        self.nodes[node_index].tri_count = 0;
+
+        self.subdivide(left_index, left_centroids, depth + 1);
+        self.subdivide(right_index, right_centroids, depth + 1);
+    }
+}
+
+// Builds a binned SAH BVH over `tris` and reorders them so every leaf covers a
+// contiguous range of the triangle buffer.
+pub fn build_bvh(vertices: &[[f32; 4]], tris: &mut [[u32; 4]]) -> Vec<BvhNode> {
+    let mut tri_bounds = Vec::with_capacity(tris.len());
+    let mut centroids = Vec::with_capacity(tris.len());
+
+    for tri in tris.iter() {
+        let mut bounds = Aabb::empty();
+        for &index in &tri[..3] {
+            let v = vertices[index as usize];
+            bounds.grow_point([v[0], v[1], v[2]]);
+        }
+        centroids.push([
+            (bounds.min[0] + bounds.max[0]) * 0.5,
+            (bounds.min[1] + bounds.max[1]) * 0.5,
+            (bounds.min[2] + bounds.max[2]) * 0.5,
+        ]);
+        tri_bounds.push(bounds);
+    }
+
+    let mut builder = BvhBuilder {
+        tri_bounds,
+        centroids,
+        indices: (0..tris.len() as u32).collect(),
+        nodes: Vec::with_capacity(tris.len() * 2),
+        depth_limited: 0,
+    };
+
+    let (root, centroid_bounds) = builder.push_node(0, tris.len() as u32);
+    builder.subdivide(root, centroid_bounds, 0);
+    if builder.depth_limited > 0 {
+        log::warn!(
+            "BVH reached its depth limit of {MAX_DEPTH}; {} leaves were left unsplit",
+            builder.depth_limited
+        );
+    }
+
+    let ordered: Vec<[u32; 4]> = builder.indices.iter().map(|&i| tris[i as usize]).collect();
+    tris.copy_from_slice(&ordered);
+
+    builder.nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A jittered grid of small triangles, enough to split several levels deep.
+    fn scattered_triangles(count: u32) -> (Vec<[f32; 4]>, Vec<[u32; 4]>) {
+        let mut state = 0x2545_f491_u32;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32
+        };
+
+        let mut vertices = Vec::new();
+        let mut tris = Vec::new();
+        for i in 0..count {
+            let origin = [
+                (i % 16) as f32 + random(),
+                (i / 16 % 16) as f32 + random(),
+                (i / 256) as f32 + random(),
+            ];
+            for _ in 0..3 {
+                vertices.push([
+                    origin[0] + random() * 0.5,
+                    origin[1] + random() * 0.5,
+                    origin[2] + random() * 0.5,
+                    1.0,
+                ]);
+            }
+            tris.push([3 * i, 3 * i + 1, 3 * i + 2, i]);
+        }
+        (vertices, tris)
+    }
+
+    fn contains(outer: &BvhNode, inner: &BvhNode) -> bool {
+        (0..3).all(|axis| {
+            outer.aabb_min[axis] <= inner.aabb_min[axis] && inner.aabb_max[axis] <= outer.aabb_max[axis]
+        })
+    }
+
+    // Calls `visit` with every node, its parent and its depth.
+    fn walk(
+        nodes: &[BvhNode],
+        index: usize,
+        parent: Option<usize>,
+        depth: u32,
+        visit: &mut impl FnMut(usize, Option<usize>, u32),
+    ) {
+        visit(index, parent, depth);
+        let node = nodes[index];
+        if node.tri_count == 0 {
+            let left = node.left_or_first as usize;
+            walk(nodes, left, Some(index), depth + 1, visit);
+            walk(nodes, left + 1, Some(index), depth + 1, visit);
+        }
+    }
+
+    #[test]
+    fn every_triangle_is_in_exactly_one_leaf() {
+        let (vertices, mut tris) = scattered_triangles(1000);
+        let nodes = build_bvh(&vertices, &mut tris);
+        assert!(nodes.len() > 1);
+
+        let mut seen = vec![0; tris.len()];
+        walk(&nodes, 0, None, 0, &mut |index, _, _| {
+            let node = nodes[index];
+            for i in node.left_or_first..node.left_or_first + node.tri_count {
+                // The last component still holds the triangle's original index.
+                seen[tris[i as usize][3] as usize] += 1;
+            }
+        });
+        assert!(seen.iter().all(|&count| count == 1), "{seen:?}");
+    }
+
+    #[test]
+    fn child_bounds_lie_inside_their_parent() {
+        let (vertices, mut tris) = scattered_triangles(1000);
+        let nodes = build_bvh(&vertices, &mut tris);
+
+        walk(&nodes, 0, None, 0, &mut |index, parent, depth| {
+            assert!(depth <= MAX_DEPTH);
+            if let Some(parent) = parent {
+                assert!(contains(&nodes[parent], &nodes[index]), "node {index} leaves parent {parent}");
+            }
+            let node = nodes[index];
+            for i in node.left_or_first..node.left_or_first + node.tri_count {
+                for &vertex in &tris[i as usize][..3] {
+                    let [x, y, z, _] = vertices[vertex as usize];
+                    let point = BvhNode {
+                        aabb_min: [x, y, z],
+                        aabb_max: [x, y, z],
+                        ..Default::default()
+                    };
+                    assert!(contains(&node, &point), "leaf {index} misses a vertex of triangle {i}");
+                }
+            }
+        });
+    }
+}
@@ -8,6 +8,9 @@ pub struct PhysicalCamera {
     pub pitch: cgmath::Deg<f32>,
     pub yaw: cgmath::Deg<f32>,
     pub position: cgmath::Point3<f32>,
+    pub fov: f32,
+    pub lens: Lens,
+    pub projection: Projection,
     aspect: f32,
     viewport_height: f32,
     sensor_pixel_size: cgmath::Vector2<f32>,
@@ -45,6 +48,115 @@ impl PhysicalCamera {
     }
 }
 
+// Thin lens focused on the plane `focus_distance` in front of the camera, in
+// scene units. An aperture radius of 0 is a pinhole with everything in focus.
+// With 3 or more blades the aperture, and so the bokeh, is a regular polygon
+// turned by `blade_rotation` degrees; otherwise it is round.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lens {
+    pub aperture: f32,
+    pub focus_distance: f32,
+    pub blades: u32,
+    pub blade_rotation: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Lens {
+            aperture: 0.0,
+            focus_distance: 10.0,
+            blades: 0,
+            blade_rotation: 0.0,
+        }
+    }
+}
+
+impl Lens {
+    // Aperture radius for an f-number, taking scene units as meters and the
+    // vertical field of view as spanning a full frame sensor, 24 mm tall.
+    pub fn aperture_for_f_stop(f_stop: f32, fov: f32) -> f32 {
+        let focal_length = 0.012 / (fov / 2.0).tan();
+        focal_length / (2.0 * f_stop)
+    }
+}
+
+// How pixels map to camera rays. Fisheyes span the vertical field of view across
+// the height of the image in a circle, equidistant ones with angle proportional
+// to the distance from the center, equisolid ones preserving solid angle. The
+// equirectangular panorama covers 360 by 180 degrees whatever the field of
+// view. Only perspective and orthographic cameras use the thin lens.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    // `height` is the extent of the image in scene units.
+    Orthographic { height: f32 },
+    Equirectangular,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+}
+
+impl Projection {
+    // Orthographic projection framing what a perspective `fov` frames `distance` away.
+    pub fn orthographic_matching(fov: f32, distance: f32) -> Projection {
+        Projection::Orthographic {
+            height: distance * 2.0 * (fov / 2.0).tan(),
+        }
+    }
+
+    // The projection after this one. Switching to orthographic keeps the framing
+    // of the perspective view at the focus distance.
+    pub fn next(self, fov: f32, focus_distance: f32) -> Projection {
+        match self {
+            Projection::Perspective => Projection::orthographic_matching(fov, focus_distance),
+            Projection::Orthographic { .. } => Projection::Equirectangular,
+            Projection::Equirectangular => Projection::FisheyeEquidistant,
+            Projection::FisheyeEquidistant => Projection::FisheyeEquisolid,
+            Projection::FisheyeEquisolid => Projection::Perspective,
+        }
+    }
+
+    fn id(self) -> u32 {
+        match self {
+            Projection::Perspective => 0,
+            Projection::Orthographic { .. } => 1,
+            Projection::Equirectangular => 2,
+            Projection::FisheyeEquidistant => 3,
+            Projection::FisheyeEquisolid => 4,
+        }
+    }
+}
+
+// Angles are in degrees except `fov`, which is the vertical field of view in radians.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraPose {
+    pub position: [f32; 3],
+    pub pitch: f32,
+    pub yaw: f32,
+    pub fov: f32,
+    pub lens: Lens,
+    pub projection: Projection,
+}
+
+impl CameraPose {
+    pub fn looking_along(position: [f32; 3], forward: [f32; 3], fov: f32) -> CameraPose {
+        let forward = cgmath::Vector3::from(forward).normalize();
+        CameraPose {
+            position,
+            pitch: (-forward.y).clamp(-1.0, 1.0).asin().to_degrees(),
+            yaw: (-forward.z).atan2(forward.x).to_degrees(),
+            fov,
+            lens: Lens::default(),
+            projection: Projection::Perspective,
+        }
+    }
+
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        let (pitch, yaw) = (self.pitch.to_radians(), self.yaw.to_radians());
+        cgmath::vec3(yaw.cos() * pitch.cos(), -pitch.sin(), -yaw.sin() * pitch.cos())
+    }
+}
+
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 
@@ -57,6 +169,12 @@ struct CameraUniform {
     _pad2: f32,
     pixel_delta_y: [f32; 3],
     _pad3: f32,
+    // x: aperture radius, y: blade count, z: blade rotation in radians, w: focus distance.
+    lens: [f32; 4],
+    forward: [f32; 3],
+    projection: u32,
+    // xy: image size in pixels, z: vertical field of view in radians.
+    projection_params: [f32; 4],
 }
 
 pub struct Camera {
@@ -75,8 +193,11 @@ impl Camera {
             pitch: cgmath::Deg(0.0),
             yaw: cgmath::Deg(90.0),
             position: (0.0, 0.0, 0.0).into(),
+            fov,
+            lens: Lens::default(),
+            projection: Projection::Perspective,
             aspect: size.width as f32 / size.height as f32,
-            viewport_height: 2.0 / f32::tan(fov / 2.0),
+            viewport_height: 2.0 * f32::tan(fov / 2.0),
             sensor_pixel_size: cgmath::vec2(size.width as f32, size.height as f32)
         };
 
@@ -100,25 +221,34 @@ impl Camera {
         camera_struct
     }
 
-    pub fn set_position(&mut self, position: cgmath::Point3<f32>, queue: &wgpu::Queue) {
-        self.camera.position = position;
+    pub fn set_fov(& mut self, fov: f32, queue: &wgpu::Queue) {
+        self.camera.fov = fov;
+        self.camera.viewport_height = 2.0 * f32::tan(fov / 2.0);
         self.build_uniform();
-        self.update_buffer(&queue);
+        self.update_buffer(queue);
+
     }
 
-    pub fn set_rotation(&mut self, pitch: f32, yaw: f32, queue: &wgpu::Queue) {
-        self.camera.yaw.0 = yaw;
-        self.camera.pitch.0 = pitch.clamp(-89.99, 89.99);
+    pub fn set_pose(&mut self, pose: &CameraPose, queue: &wgpu::Queue) {
+        self.camera.position = pose.position.into();
+        self.camera.yaw.0 = pose.yaw;
+        self.camera.pitch.0 = pose.pitch.clamp(-89.99, 89.99);
         self.camera.set_axes();
-        self.build_uniform();
-        self.update_buffer(&queue);
+        self.camera.lens = pose.lens;
+        self.camera.projection = pose.projection;
+        self.set_fov(pose.fov, queue);
     }
 
-    pub fn set_fov(& mut self, fov: f32, queue: &wgpu::Queue) {
-        self.camera.viewport_height = 2.0 / f32::tan(fov / 2.0);
-        self.build_uniform();
-        self.update_buffer(&queue);
-
+    // The camera as last set, in the form `set_pose` takes.
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.camera.position.into(),
+            pitch: self.camera.pitch.0,
+            yaw: self.camera.yaw.0,
+            fov: self.camera.fov,
+            lens: self.camera.lens,
+            projection: self.camera.projection,
+        }
     }
     
     pub fn update_buffer(&self, queue: &wgpu::Queue) {
@@ -129,16 +259,26 @@ impl Camera {
         );     
     }
 
+    // Perspective pixels are laid out on the focal plane, which rays from anywhere
+    // on the lens pass through in focus. Orthographic pixels are laid out on the
+    // plane through the camera that their parallel rays start from. The other
+    // projections only use the pixel deltas for the camera's axes.
     pub fn build_uniform(&mut self) {
-        let viewport_width = self.camera.viewport_height * self.camera.aspect;
+        let lens = self.camera.lens;
+        let focus = lens.focus_distance.max(1e-4);
+        let (plane_distance, viewport_height) = match self.camera.projection {
+            Projection::Orthographic { height } => (0.0, height),
+            _ => (focus, self.camera.viewport_height * focus),
+        };
+        let viewport_width = viewport_height * self.camera.aspect;
 
         let viewport_u = self.camera.right * viewport_width;
-        let viewport_v = self.camera.up * -self.camera.viewport_height;
+        let viewport_v = self.camera.up * -viewport_height;
 
         let pixel_delta_x = viewport_u / self.camera.sensor_pixel_size.x;
         let pixel_delta_y = viewport_v / self.camera.sensor_pixel_size.y;
 
-        let lower_left = self.camera.position + self.camera.forward * 1.0 - viewport_u * 0.5 - viewport_v * 0.5;
+        let lower_left = self.camera.position + self.camera.forward * plane_distance - viewport_u * 0.5 - viewport_v * 0.5;
         let lower_left_pixel: [f32; 3] =
             (lower_left + 0.5 * (pixel_delta_x - pixel_delta_y)).into();
 
@@ -152,6 +292,15 @@ impl Camera {
             _pad2: 0.0,
             pixel_delta_y: pixel_delta_y.into(),
             _pad3: 0.0,
+            lens: [lens.aperture, lens.blades as f32, lens.blade_rotation.to_radians(), focus],
+            forward: self.camera.forward.into(),
+            projection: self.camera.projection.id(),
+            projection_params: [
+                self.camera.sensor_pixel_size.x,
+                self.camera.sensor_pixel_size.y,
+                self.camera.fov.min(std::f32::consts::TAU),
+                0.0,
+            ],
         }
     }
 }
\ No newline at end of file
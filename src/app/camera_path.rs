@@ -0,0 +1,361 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::CameraPose;
+
+// Spacing of keyframes recorded live, in seconds.
+pub const RECORDED_KEYFRAME_INTERVAL: f32 = 2.0;
+
+#[derive(Debug)]
+pub enum CameraPathError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    NoKeyframes,
+    // Keyframe times have to increase strictly.
+    Unordered { index: usize, time: f32 },
+}
+
+impl fmt::Display for CameraPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraPathError::Io(err) => write!(f, "failed to access camera path file: {err}"),
+            CameraPathError::Parse(err) => write!(f, "invalid camera path file: {err}"),
+            CameraPathError::Serialize(err) => write!(f, "failed to write camera path: {err}"),
+            CameraPathError::NoKeyframes => write!(f, "camera path has no keyframes"),
+            CameraPathError::Unordered { index, time } => {
+                write!(f, "keyframe {index} at {time} s does not come after the one before it")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CameraPathError {}
+
+impl From<std::io::Error> for CameraPathError {
+    fn from(err: std::io::Error) -> Self {
+        CameraPathError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for CameraPathError {
+    fn from(err: toml::de::Error) -> Self {
+        CameraPathError::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for CameraPathError {
+    fn from(err: toml::ser::Error) -> Self {
+        CameraPathError::Serialize(err)
+    }
+}
+
+// Catmull-Rom passes through every keyframe at its time. Bezier treats the
+// keyframes as the control points of a single curve, which only passes through
+// the first and last and ignores the times of the others.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Linear,
+    #[default]
+    CatmullRom,
+    Bezier,
+}
+
+// Timing curve over the whole path, so a turntable can run at constant speed or
+// start and stop gently.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ease {
+    #[default]
+    Linear,
+    In,
+    Out,
+    InOut,
+}
+
+impl Ease {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Ease::Linear => t,
+            Ease::In => t * t * t,
+            Ease::Out => 1.0 - (1.0 - t).powi(3),
+            Ease::InOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+// Angles are in degrees, including the vertical `fov`, as in scene files. Yaw is
+// not wrapped, so a full turn goes from 0 to 360.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    // Seconds from the start of the path.
+    pub time: f32,
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub pitch: f32,
+    pub yaw: f32,
+    pub fov: f32,
+    pub focus_distance: f32,
+}
+
+impl Keyframe {
+    pub fn from_pose(time: f32, pose: &CameraPose) -> Keyframe {
+        Keyframe {
+            time,
+            position: pose.position,
+            pitch: pose.pitch,
+            yaw: pose.yaw,
+            fov: pose.fov.to_degrees(),
+            focus_distance: pose.lens.focus_distance,
+        }
+    }
+
+    // Everything that is interpolated, as one vector.
+    fn values(&self) -> [f32; 7] {
+        let [x, y, z] = self.position;
+        [x, y, z, self.pitch, self.yaw, self.fov, self.focus_distance]
+    }
+}
+
+// Keyframed camera animation, stored as TOML:
+//
+//     interpolation = "catmull_rom"
+//     ease = "in_out"
+//
+//     [[keyframes]]
+//     time = 0.0
+//     position = [0.0, -2.0, 12.0]
+//     yaw = 90.0
+//     fov = 60.0
+//     focus_distance = 12.0
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraPath {
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub ease: Ease,
+    #[serde(default)]
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<CameraPath, CameraPathError> {
+        let text = std::fs::read_to_string(path)?;
+        let camera_path: CameraPath = toml::from_str(&text)?;
+        camera_path.validate()?;
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CameraPathError> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), CameraPathError> {
+        if self.keyframes.is_empty() {
+            return Err(CameraPathError::NoKeyframes);
+        }
+        for (index, pair) in self.keyframes.windows(2).enumerate() {
+            if pair[1].time <= pair[0].time {
+                return Err(CameraPathError::Unordered {
+                    index: index + 1,
+                    time: pair[1].time,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |key| key.time)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |key| key.time) - self.start()
+    }
+
+    // Appends a keyframe at the pose, `RECORDED_KEYFRAME_INTERVAL` after the last.
+    pub fn record(&mut self, pose: &CameraPose) {
+        let time = self
+            .keyframes
+            .last()
+            .map_or(0.0, |key| key.time + RECORDED_KEYFRAME_INTERVAL);
+        self.keyframes.push(Keyframe::from_pose(time, pose));
+    }
+
+    // Camera at `time` seconds into the path, clamped to its ends. Only position,
+    // rotation, field of view and focus are animated; the rest of the lens and
+    // the projection come from `base`. None without keyframes.
+    pub fn pose_at(&self, time: f32, base: &CameraPose) -> Option<CameraPose> {
+        if self.keyframes.is_empty() {
+            return None;
+        }
+        let duration = self.duration();
+        let progress = if duration > 0.0 {
+            self.ease.apply(((time - self.start()) / duration).clamp(0.0, 1.0))
+        } else {
+            0.0
+        };
+
+        let [x, y, z, pitch, yaw, fov, focus_distance] = match self.interpolation {
+            Interpolation::Bezier => self.bezier(progress),
+            Interpolation::Linear | Interpolation::CatmullRom => self.spline(self.start() + progress * duration),
+        };
+        let mut pose = *base;
+        pose.position = [x, y, z];
+        pose.pitch = pitch;
+        pose.yaw = yaw;
+        pose.fov = fov.to_radians();
+        pose.lens.focus_distance = focus_distance;
+        Some(pose)
+    }
+
+    // Piecewise interpolation through the keyframes. Catmull-Rom segments are
+    // cubic Hermite curves with tangents from the neighboring keyframes, scaled
+    // by their times so speed stays continuous across unevenly spaced keyframes.
+    fn spline(&self, time: f32) -> [f32; 7] {
+        let keys = &self.keyframes;
+        if keys.len() == 1 {
+            return keys[0].values();
+        }
+        let next = keys.partition_point(|key| key.time <= time).clamp(1, keys.len() - 1);
+        let (a, b) = (&keys[next - 1], &keys[next]);
+        let span = b.time - a.time;
+        let t = ((time - a.time) / span).clamp(0.0, 1.0);
+        let (p0, p1) = (a.values(), b.values());
+
+        if self.interpolation == Interpolation::Linear {
+            return std::array::from_fn(|i| p0[i] + (p1[i] - p0[i]) * t);
+        }
+
+        // Finite difference slope around keyframe `i`, one sided at the ends.
+        let slope = |i: usize| -> [f32; 7] {
+            let before = &keys[i.saturating_sub(1)];
+            let after = &keys[(i + 1).min(keys.len() - 1)];
+            let (v0, v1) = (before.values(), after.values());
+            std::array::from_fn(|c| (v1[c] - v0[c]) / (after.time - before.time))
+        };
+        let (m0, m1) = (slope(next - 1), slope(next));
+
+        let (t2, t3) = (t * t, t * t * t);
+        let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+        let h10 = t3 - 2.0 * t2 + t;
+        let h01 = -2.0 * t3 + 3.0 * t2;
+        let h11 = t3 - t2;
+        std::array::from_fn(|i| h00 * p0[i] + h10 * span * m0[i] + h01 * p1[i] + h11 * span * m1[i])
+    }
+
+    // De Casteljau's algorithm over all keyframes at `t` in 0..=1.
+    fn bezier(&self, t: f32) -> [f32; 7] {
+        let mut points: Vec<[f32; 7]> = self.keyframes.iter().map(Keyframe::values).collect();
+        for level in (1..points.len()).rev() {
+            for i in 0..level {
+                let (p0, p1) = (points[i], points[i + 1]);
+                points[i] = std::array::from_fn(|c| p0[c] + (p1[c] - p0[c]) * t);
+            }
+        }
+        points[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, yaw: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position: [x, 0.5 * x * x, -x],
+            pitch: x * 2.0,
+            yaw,
+            fov: 40.0 + x,
+            focus_distance: 5.0 + x,
+        }
+    }
+
+    // Unevenly spaced, with a wide turn, so segments differ in length and speed.
+    fn path(interpolation: Interpolation) -> CameraPath {
+        CameraPath {
+            interpolation,
+            ease: Ease::Linear,
+            keyframes: vec![
+                keyframe(1.0, 0.0, 0.0),
+                keyframe(1.5, 2.0, 90.0),
+                keyframe(4.0, -1.0, 200.0),
+                keyframe(4.25, 3.0, 360.0),
+            ],
+        }
+    }
+
+    fn values_at(path: &CameraPath, time: f32) -> [f32; 7] {
+        let base = CameraPose::looking_along([0.0; 3], [0.0, 0.0, 1.0], 1.0);
+        let pose = path.pose_at(time, &base).unwrap();
+        let [x, y, z] = pose.position;
+        [x, y, z, pose.pitch, pose.yaw, pose.fov.to_degrees(), pose.lens.focus_distance]
+    }
+
+    fn assert_close(actual: [f32; 7], expected: [f32; 7]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= 1e-3 * e.abs().max(1.0), "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn passes_through_keyframes_at_their_times() {
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let path = path(interpolation);
+            for key in &path.keyframes {
+                assert_close(values_at(&path, key.time), key.values());
+            }
+        }
+    }
+
+    #[test]
+    fn linear_and_catmull_rom_agree_at_keyframes() {
+        let (linear, smooth) = (path(Interpolation::Linear), path(Interpolation::CatmullRom));
+        for key in &linear.keyframes {
+            assert_close(values_at(&smooth, key.time), values_at(&linear, key.time));
+        }
+        // Between keyframes the curves differ.
+        assert_ne!(values_at(&smooth, 2.0), values_at(&linear, 2.0));
+    }
+
+    #[test]
+    fn clamps_to_the_ends() {
+        let path = path(Interpolation::CatmullRom);
+        assert_close(values_at(&path, 0.0), path.keyframes[0].values());
+        assert_close(values_at(&path, 10.0), path.keyframes[3].values());
+    }
+
+    #[test]
+    fn rejects_unordered_times() {
+        let mut path = path(Interpolation::CatmullRom);
+        assert!(path.validate().is_ok());
+
+        path.keyframes[2].time = 1.2;
+        assert!(matches!(path.validate(), Err(CameraPathError::Unordered { index: 2, time }) if time == 1.2));
+
+        // Repeated times leave no span to interpolate over.
+        path.keyframes[2].time = 1.5;
+        assert!(matches!(path.validate(), Err(CameraPathError::Unordered { index: 2, .. })));
+
+        path.keyframes.clear();
+        assert!(matches!(path.validate(), Err(CameraPathError::NoKeyframes)));
+    }
+
+    #[test]
+    fn load_rejects_unordered_times() {
+        let text = "[[keyframes]]\ntime = 1.0\nposition = [0.0, 0.0, 0.0]\nyaw = 0.0\nfov = 60.0\nfocus_distance = 1.0\n\n\
+                    [[keyframes]]\ntime = 0.5\nposition = [1.0, 0.0, 0.0]\nyaw = 0.0\nfov = 60.0\nfocus_distance = 1.0\n";
+        let file = std::env::temp_dir().join(format!("camera_path_unordered_{}.toml", std::process::id()));
+        std::fs::write(&file, text).unwrap();
+        let result = CameraPath::load(&file);
+        std::fs::remove_file(&file).unwrap();
+        assert!(matches!(result, Err(CameraPathError::Unordered { index: 1, .. })));
+    }
+}
@@ -0,0 +1,215 @@
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+use crate::app::scene_file::RenderDescription;
+
+// Operators mapping linear HDR radiance to the displayable 0..=1 range.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    #[default]
+    Clamp = 0,
+    Reinhard = 1,
+    // Stephen Hill's fit of the ACES reference rendering and output transforms.
+    Aces = 2,
+    Agx = 3,
+}
+
+impl Tonemap {
+    pub fn next(self) -> Tonemap {
+        match self {
+            Tonemap::Clamp => Tonemap::Reinhard,
+            Tonemap::Reinhard => Tonemap::Aces,
+            Tonemap::Aces => Tonemap::Agx,
+            Tonemap::Agx => Tonemap::Clamp,
+        }
+    }
+}
+
+// Display transform applied when the accumulated image is shown or saved as a
+// PNG; the accumulation itself stays linear. `exposure` is in stops and
+// `white_balance` is the color temperature in kelvin that is mapped to white.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DisplaySettings {
+    pub tonemap: Tonemap,
+    pub exposure: f32,
+    pub white_balance: f32,
+}
+
+const NEUTRAL_WHITE_BALANCE: f32 = 6500.0;
+// Below this the black body leaves the sRGB gamut.
+pub const MIN_WHITE_BALANCE: f32 = 2000.0;
+pub const MAX_WHITE_BALANCE: f32 = 25000.0;
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            tonemap: Tonemap::Clamp,
+            exposure: 0.0,
+            white_balance: NEUTRAL_WHITE_BALANCE,
+        }
+    }
+}
+
+impl DisplaySettings {
+    pub fn apply_render_settings(&mut self, render: &RenderDescription) {
+        if let Some(tonemap) = render.tonemap {
+            self.tonemap = tonemap;
+        }
+        if let Some(exposure) = render.exposure {
+            self.exposure = exposure;
+        }
+        if let Some(white_balance) = render.white_balance {
+            self.white_balance = white_balance;
+        }
+    }
+
+    // Exposure and white balance as a single multiplier per channel.
+    fn scale(&self) -> [f32; 3] {
+        let exposure = self.exposure.exp2();
+        white_balance_gains(self.white_balance).map(|gain| gain * exposure)
+    }
+
+    fn uniform(&self) -> DisplayUniform {
+        let [r, g, b] = self.scale();
+        DisplayUniform {
+            scale: [r, g, b, 0.0],
+            tonemap: [self.tonemap as u32, 0, 0, 0],
+        }
+    }
+
+    // CPU version of `fs_main` in raster.wgsl, returning linear values in 0..=1.
+    pub fn apply(&self, pixel: [f32; 4]) -> [f32; 4] {
+        let scale = self.scale();
+        let color = [pixel[0] * scale[0], pixel[1] * scale[1], pixel[2] * scale[2]];
+        let [r, g, b] = match self.tonemap {
+            Tonemap::Clamp => color,
+            Tonemap::Reinhard => color.map(|c| c / (1.0 + c)),
+            Tonemap::Aces => aces_fitted(color),
+            Tonemap::Agx => agx(color),
+        };
+        [r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0), pixel[3]]
+    }
+}
+
+fn mul(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+// Matrices are row major here and column major in raster.wgsl.
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces_fitted(color: [f32; 3]) -> [f32; 3] {
+    let v = mul(&ACES_INPUT, color)
+        .map(|v| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081));
+    mul(&ACES_OUTPUT, v)
+}
+
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.84247906, 0.0784336, 0.07922375],
+    [0.04232824, 0.87846864, 0.07916613],
+    [0.04237565, 0.0784336, 0.879143],
+];
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196879, -0.09802088, -0.09902974],
+    [-0.05289685, 1.1519031, -0.09896118],
+    [-0.05297164, -0.09804345, 1.1510737],
+];
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+// AgX base look with the polynomial fit of its contrast curve. The curve outputs
+// display encoded values, which are decoded with a 2.2 gamma.
+fn agx(color: [f32; 3]) -> [f32; 3] {
+    let encoded = mul(&AGX_INSET, color).map(|v| {
+        let x = (v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    mul(&AGX_OUTSET, encoded).map(|v| v.max(0.0).powf(2.2))
+}
+
+// Chromaticity of a black body (Kang et al. 2002), valid from 1667 K to 25000 K.
+fn planckian_xy(kelvin: f32) -> (f32, f32) {
+    let t = kelvin as f64;
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+    (x as f32, y as f32)
+}
+
+// Linear sRGB color of a black body at unit luminance.
+fn blackbody_rgb(kelvin: f32) -> [f32; 3] {
+    let (x, y) = planckian_xy(kelvin);
+    let xyz = [x / y, 1.0, (1.0 - x - y) / y];
+    mul(
+        &[
+            [3.2404542, -1.5371385, -0.4985314],
+            [-0.969266, 1.8760108, 0.041556],
+            [0.0556434, -0.2040259, 1.0572252],
+        ],
+        xyz,
+    )
+}
+
+// Per channel gains that turn light of the given temperature neutral, scaled to
+// keep the luminance of grays.
+fn white_balance_gains(kelvin: f32) -> [f32; 3] {
+    let illuminant = blackbody_rgb(kelvin.clamp(MIN_WHITE_BALANCE, MAX_WHITE_BALANCE));
+    let white = blackbody_rgb(NEUTRAL_WHITE_BALANCE);
+    let gains = [0, 1, 2].map(|i| white[i] / illuminant[i]);
+    let luminance = 0.2126 * gains[0] + 0.7152 * gains[1] + 0.0722 * gains[2];
+    gains.map(|gain| gain / luminance)
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DisplayUniform {
+    scale: [f32; 4],
+    // x: `Tonemap`
+    tonemap: [u32; 4],
+}
+
+// Display settings and the uniform buffer the fragment pass reads them from.
+// Changing them does not touch the accumulated image.
+pub struct Display {
+    pub settings: DisplaySettings,
+    pub buffer: wgpu::Buffer,
+}
+
+impl Display {
+    pub fn new(device: &wgpu::Device, settings: DisplaySettings) -> Display {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Display Buffer"),
+            contents: bytemuck::cast_slice(&[settings.uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Display { settings, buffer }
+    }
+
+    pub fn set(&mut self, settings: DisplaySettings, queue: &wgpu::Queue) {
+        self.settings = settings;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[settings.uniform()]));
+    }
+}
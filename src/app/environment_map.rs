@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use crate::scene::luminance;
+
+// Equirectangular radiance map. Row 0 is the zenith (-Y in scene space).
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl EnvironmentMap {
+    // Reads a Radiance .hdr or an OpenEXR file.
+    pub fn load(path: &Path) -> Result<EnvironmentMap, image::ImageError> {
+        let image = image::open(path)?.to_rgb32f();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels().map(|p| p.0).collect();
+        log::info!("Loaded {width}x{height} environment map {}", path.display());
+
+        Ok(EnvironmentMap {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // Packs the map for the shader: `width * height` texels holding the radiance in
+    // xyz and the running sum of the sampling weight along their row in w, followed
+    // by `height` entries holding the running sum of the row totals in x. Weights
+    // are luminance times sin(theta) so rows near the poles, which cover less solid
+    // angle, are picked less often.
+    pub fn sampling_table(&self) -> Vec<[f32; 4]> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut table = Vec::with_capacity(width * height + height);
+        let mut marginal = Vec::with_capacity(height);
+        let mut total = 0.0;
+
+        for y in 0..height {
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * std::f32::consts::PI).sin();
+            let mut row = 0.0;
+            for pixel in &self.pixels[y * width..(y + 1) * width] {
+                row += luminance(*pixel) * sin_theta;
+                table.push([pixel[0], pixel[1], pixel[2], row]);
+            }
+            total += row;
+            marginal.push([total, 0.0, 0.0, 0.0]);
+        }
+
+        table.extend(marginal);
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    // As in compute.wgsl.
+    const MIN_CDF_TARGET: f32 = 1e-7;
+
+    // Black along the top row and the first two columns, with some black texels
+    // scattered in between.
+    fn map() -> EnvironmentMap {
+        let (width, height) = (32, 16);
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                if y == 0 || x < 2 {
+                    [0.0; 3]
+                } else {
+                    [((x * 7 + y * 3) % 5) as f32, 0.5, 0.25]
+                }
+            })
+            .collect();
+        EnvironmentMap { width, height, pixels }
+    }
+
+    struct Table {
+        width: usize,
+        height: usize,
+        entries: Vec<[f32; 4]>,
+    }
+
+    impl Table {
+        fn row_sum(&self, x: usize, y: usize) -> f32 {
+            self.entries[y * self.width + x][3]
+        }
+
+        fn weight(&self, x: usize, y: usize) -> f32 {
+            self.row_sum(x, y) - if x > 0 { self.row_sum(x - 1, y) } else { 0.0 }
+        }
+
+        fn marginal(&self) -> &[[f32; 4]] {
+            &self.entries[self.width * self.height..]
+        }
+
+        fn total(&self) -> f32 {
+            self.marginal()[self.height - 1][0]
+        }
+
+        // The texel `sample_environment` picks for uniform random numbers `u`.
+        fn pick(&self, u: [f32; 2]) -> (usize, usize) {
+            let target_row = u[0].max(MIN_CDF_TARGET) * self.total();
+            let y = self.marginal().partition_point(|m| m[0] < target_row).min(self.height - 1);
+            let target_texel = u[1].max(MIN_CDF_TARGET) * self.row_sum(self.width - 1, y);
+            let row = &self.entries[y * self.width..(y + 1) * self.width];
+            let x = row.partition_point(|texel| texel[3] < target_texel).min(self.width - 1);
+            (x, y)
+        }
+    }
+
+    fn table() -> Table {
+        let map = map();
+        Table {
+            width: map.width as usize,
+            height: map.height as usize,
+            entries: map.sampling_table(),
+        }
+    }
+
+    #[test]
+    fn cdfs_are_monotonic_and_end_at_the_total() {
+        let table = table();
+        assert_eq!(table.entries.len(), table.width * table.height + table.height);
+
+        let mut row_totals = 0.0;
+        for y in 0..table.height {
+            for x in 1..table.width {
+                assert!(table.row_sum(x, y) >= table.row_sum(x - 1, y), "texel {x}, {y}");
+            }
+            row_totals += table.row_sum(table.width - 1, y);
+        }
+        for pair in table.marginal().windows(2) {
+            assert!(pair[1][0] >= pair[0][0], "{pair:?}");
+        }
+        assert!((table.total() / row_totals - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let table = table();
+        let (width, height) = (table.width as f32, table.height as f32);
+        let mut integral = 0.0;
+        for y in 0..table.height {
+            // As `environment_pdf` computes it at the texel's center, times the
+            // solid angle the texel covers.
+            let sin_theta = ((y as f32 + 0.5) / height * PI).sin();
+            let solid_angle =
+                2.0 * PI / width * ((y as f32 / height * PI).cos() - ((y + 1) as f32 / height * PI).cos());
+            for x in 0..table.width {
+                let texel_pdf = table.weight(x, y) / table.total();
+                integral += texel_pdf * width * height / (2.0 * PI * PI * sin_theta) * solid_angle;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2, "{integral}");
+    }
+
+    #[test]
+    fn black_texels_are_never_picked() {
+        let table = table();
+        let steps = 256;
+        for i in 0..=steps {
+            for j in 0..=steps {
+                let (x, y) = table.pick([i as f32 / steps as f32, j as f32 / steps as f32]);
+                assert!(table.weight(x, y) > 0.0, "picked black texel {x}, {y}");
+            }
+        }
+    }
+}
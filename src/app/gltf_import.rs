@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::camera::CameraPose;
+use crate::mesh::*;
+use crate::image_texture::ImageTexture;
+use crate::material::{Material, Principled, TextureSlot};
+use crate::scene::luminance;
+
+pub struct GltfScene {
+    pub mesh: Mesh,
+    pub camera: Option<CameraPose>,
+}
+
+// A perspective camera in glTF space: origin, forward direction and vertical FOV.
+struct SourceCamera {
+    origin: [f32; 3],
+    forward: [f32; 3],
+    yfov: f32,
+}
+
+struct GltfImporter<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    mesh: Mesh,
+    camera: Option<SourceCamera>,
+    material_slots: HashMap<Option<usize>, u32>,
+    // Keyed by image index and whether it holds sRGB color.
+    texture_slots: HashMap<(usize, bool), u32>,
+}
+
+// Metallic-roughness PBR maps directly onto the principled BSDF. The gltf crate
+// does not model clearcoat or sheen, so those extensions are read from the raw JSON.
+// Materials with parameters outside the ranges glTF allows fall back to diffuse.
+fn convert_material(material: &gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let strength = material.emissive_strength().unwrap_or(1.0);
+    let mut transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+    if material.alpha_mode() == gltf::material::AlphaMode::Blend {
+        transmission = transmission.max(1.0 - alpha);
+    }
+    let clearcoat = material.extension_value("KHR_materials_clearcoat");
+    let sheen = material.extension_value("KHR_materials_sheen");
+    // Sheen color is reduced to its luminance since the principled sheen can
+    // only be tinted towards the base color.
+    let sheen_color = extension_array(sheen, "sheenColorFactor").unwrap_or([0.0; 3]);
+
+    let params = Principled {
+        base_color: [r, g, b],
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        // The specular factor scales the 4% dielectric reflectance, which is a
+        // principled specular of 0.5.
+        specular: 0.5 * material.specular().map_or(1.0, |s| s.specular_factor()),
+        transmission,
+        clearcoat: extension_factor(clearcoat, "clearcoatFactor").unwrap_or(0.0),
+        clearcoat_roughness: extension_factor(clearcoat, "clearcoatRoughnessFactor").unwrap_or(0.0),
+        sheen: luminance(sheen_color),
+        sheen_tint: 0.0,
+        // KHR_materials_ior also allows 0, which has no refracting equivalent.
+        ior: material.ior().filter(|&ior| ior > 1.0).unwrap_or(1.5),
+        emission: material.emissive_factor().map(|c| c * strength),
+    };
+    Material::principled(&params).unwrap_or_else(|err| {
+        log::warn!("glTF material {}: {err}, using diffuse instead", material.name().unwrap_or("<unnamed>"));
+        let diffuse = Material::diffuse(params.base_color);
+        diffuse.with_emission(params.emission).unwrap_or(diffuse)
+    })
+}
+
+fn extension_factor(extension: Option<&gltf::json::Value>, key: &str) -> Option<f32> {
+    Some(extension?.get(key)?.as_f64()? as f32)
+}
+
+fn extension_array(extension: Option<&gltf::json::Value>, key: &str) -> Option<[f32; 3]> {
+    let values = extension?.get(key)?.as_array()?;
+    match values.as_slice() {
+        [r, g, b] => Some([r.as_f64()? as f32, g.as_f64()? as f32, b.as_f64()? as f32]),
+        _ => None,
+    }
+}
+
+// Widens any of the 8, 16 or 32 bit formats glTF images decode to into RGBA8.
+// One and two channel images are grey, with alpha in the second channel.
+fn convert_image(data: &gltf::image::Data, srgb: bool) -> ImageTexture {
+    use gltf::image::Format;
+    let (channels, bytes) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let channel = |c: &[u8]| -> u8 {
+        match bytes {
+            1 => c[0],
+            2 => (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8,
+            _ => (f32::from_ne_bytes([c[0], c[1], c[2], c[3]]).clamp(0.0, 1.0) * 255.0).round() as u8,
+        }
+    };
+
+    let pixels = data
+        .pixels
+        .chunks_exact(channels * bytes)
+        .map(|pixel| {
+            let c: Vec<u8> = pixel.chunks_exact(bytes).map(channel).collect();
+            match channels {
+                1 => [c[0], c[0], c[0], 255],
+                2 => [c[0], c[0], c[0], c[1]],
+                3 => [c[0], c[1], c[2], 255],
+                _ => [c[0], c[1], c[2], c[3]],
+            }
+        })
+        .collect();
+
+    ImageTexture {
+        width: data.width,
+        height: data.height,
+        pixels,
+        srgb,
+    }
+}
+
+impl GltfImporter<'_> {
+    fn texture_slot(&mut self, texture: gltf::Texture, srgb: bool) -> Option<u32> {
+        let image = texture.source().index();
+        if let Some(&slot) = self.texture_slots.get(&(image, srgb)) {
+            return Some(slot);
+        }
+
+        let data = self.images.get(image)?;
+        self.mesh.textures.push(convert_image(data, srgb));
+        let slot = self.mesh.textures.len() as u32 - 1;
+        self.texture_slots.insert((image, srgb), slot);
+        Some(slot)
+    }
+
+    // Only the first UV set is imported, so textures using another are skipped.
+    fn with_textures(&mut self, source: &gltf::Material, mut material: Material) -> Material {
+        let pbr = source.pbr_metallic_roughness();
+        let metallic_roughness = pbr.metallic_roughness_texture().map(|info| (info.texture(), info.tex_coord()));
+        let textures = [
+            (TextureSlot::Albedo, pbr.base_color_texture().map(|info| (info.texture(), info.tex_coord())), true),
+            (TextureSlot::Roughness, metallic_roughness.clone(), false),
+            (TextureSlot::Metallic, metallic_roughness, false),
+            (TextureSlot::Normal, source.normal_texture().map(|info| (info.texture(), info.tex_coord())), false),
+        ];
+
+        for (slot, texture, srgb) in textures {
+            let Some((texture, tex_coord)) = texture else {
+                continue;
+            };
+            if tex_coord != 0 {
+                log::warn!("Skipping glTF texture using UV set {tex_coord}");
+                continue;
+            }
+            if let Some(index) = self.texture_slot(texture, srgb) {
+                material = material.with_texture(slot, index);
+            }
+        }
+        material
+    }
+
+    fn material_slot(&mut self, material: gltf::Material) -> u32 {
+        if let Some(&slot) = self.material_slots.get(&material.index()) {
+            return slot;
+        }
+
+        let converted = self.with_textures(&material, convert_material(&material));
+        self.mesh.materials.push(converted);
+        self.mesh
+            .material_names
+            .push(material.name().unwrap_or("default").to_string());
+        let slot = self.mesh.materials.len() as u32 - 1;
+        self.material_slots.insert(material.index(), slot);
+        slot
+    }
+
+    fn visit_node(&mut self, node: gltf::Node, parent: Matrix4<f32>) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.add_primitive(&primitive, &transform);
+            }
+        }
+
+        if self.camera.is_none() {
+            if let Some(camera) = node.camera() {
+                self.camera = convert_camera(&camera, &transform);
+            }
+        }
+
+        for child in node.children() {
+            self.visit_node(child, transform);
+        }
+    }
+
+    fn add_primitive(&mut self, primitive: &gltf::Primitive, transform: &Matrix4<f32>) {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            log::warn!("Skipping glTF primitive with unsupported mode {:?}", primitive.mode());
+            return;
+        }
+
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            log::warn!("Skipping glTF primitive without positions");
+            return;
+        };
+
+        let normal_matrix = {
+            let m = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+            m.invert().unwrap_or(m).transpose()
+        };
+        let base = self.mesh.vertices.len() as u32;
+
+        for position in positions {
+            let p = transform * Vector4::new(position[0], position[1], position[2], 1.0);
+            self.mesh.vertices.push([p.x, p.y, p.z, 1.0]);
+        }
+        let count = self.mesh.vertices.len() - base as usize;
+
+        match reader.read_normals() {
+            Some(normals) => {
+                for normal in normals {
+                    let n = (normal_matrix * Vector3::from(normal)).normalize();
+                    self.mesh.normals.push([n.x, n.y, n.z, 0.0]);
+                }
+            }
+            None => self.mesh.normals.extend(std::iter::repeat_n([0.0; 4], count)),
+        }
+
+        match reader.read_tex_coords(0) {
+            Some(uvs) => self.mesh.uvs.extend(uvs.into_f32()),
+            None => self.mesh.uvs.extend(std::iter::repeat_n([0.0; 2], count)),
+        }
+
+        let material = self.material_slot(primitive.material());
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..count as u32).collect(),
+        };
+
+        // Mirroring transforms turn counter-clockwise faces clockwise, so glTF
+        // requires reversing their winding.
+        let mirrored = transform.determinant() < 0.0;
+        for tri in indices.chunks_exact(3) {
+            let (b, c) = if mirrored { (tri[2], tri[1]) } else { (tri[1], tri[2]) };
+            self.mesh
+                .triangles
+                .push([base + tri[0], base + b, base + c, material]);
+        }
+    }
+}
+
+// glTF cameras look down their local -Z axis with +Y up.
+fn convert_camera(camera: &gltf::Camera, transform: &Matrix4<f32>) -> Option<SourceCamera> {
+    let gltf::camera::Projection::Perspective(perspective) = camera.projection() else {
+        log::warn!("Skipping orthographic glTF camera");
+        return None;
+    };
+
+    let origin = transform * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let forward = (transform * Vector4::new(0.0, 0.0, -1.0, 0.0)).truncate().normalize();
+
+    Some(SourceCamera {
+        origin: [origin.x, origin.y, origin.z],
+        forward: forward.into(),
+        yfov: perspective.yfov(),
+    })
+}
+
+fn camera_pose(camera: &SourceCamera, transform: &ImportTransform) -> CameraPose {
+    CameraPose::looking_along(
+        transform.point(camera.origin),
+        transform.direction(camera.forward),
+        camera.yfov,
+    )
+}
+
+// Loads a .gltf (with external or embedded buffers) or .glb file and flattens the
+// default scene's node hierarchy into a single mesh.
+pub fn load_gltf(path: &str, options: &ImportOptions) -> Result<GltfScene, gltf::Error> {
+    let (document, buffers, images) = gltf::import(path)?;
+
+    let mut importer = GltfImporter {
+        buffers: &buffers,
+        images: &images,
+        mesh: Mesh::default(),
+        camera: None,
+        material_slots: HashMap::new(),
+        texture_slots: HashMap::new(),
+    };
+
+    match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => {
+            for node in scene.nodes() {
+                importer.visit_node(node, Matrix4::identity());
+            }
+        }
+        None => log::warn!("{path} contains no scenes"),
+    }
+
+    let mut mesh = importer.mesh;
+    let transform = options.apply(&mut mesh);
+
+    Ok(GltfScene {
+        mesh,
+        camera: importer.camera.map(|camera| camera_pose(&camera, &transform)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::{assert_outward, tetrahedron};
+
+    // Writes the test tetrahedron as a glTF file whose mesh sits under a chain of
+    // nodes with the given scales, and loads it.
+    fn load_tetrahedron(name: &str, scales: &[[f32; 3]]) -> Mesh {
+        let source = tetrahedron();
+        let mut bin: Vec<u8> = Vec::new();
+        for v in &source.vertices {
+            bin.extend(v[..3].iter().flat_map(|c| c.to_le_bytes()));
+        }
+        for tri in &source.triangles {
+            bin.extend(tri[..3].iter().flat_map(|&i| (i as u16).to_le_bytes()));
+        }
+
+        let nodes: Vec<String> = scales
+            .iter()
+            .enumerate()
+            .map(|(i, scale)| {
+                let link = if i + 1 < scales.len() {
+                    format!("\"children\": [{}]", i + 1)
+                } else {
+                    "\"mesh\": 0".to_string()
+                };
+                format!("{{ \"scale\": [{}, {}, {}], {link} }}", scale[0], scale[1], scale[2])
+            })
+            .collect();
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [{nodes}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+                "buffers": [{{ "uri": "{name}.bin", "byteLength": {length} }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 48 }},
+                    {{ "buffer": 0, "byteOffset": 48, "byteLength": 24 }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                       "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 1.0] }},
+                    {{ "bufferView": 1, "componentType": 5123, "count": 12, "type": "SCALAR" }}
+                ]
+            }}"#,
+            nodes = nodes.join(", "),
+            length = bin.len(),
+        );
+
+        let dir = std::env::temp_dir().join(format!("gltf_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{name}.bin")), &bin).unwrap();
+        let path = dir.join(format!("{name}.gltf"));
+        std::fs::write(&path, json).unwrap();
+        let scene = load_gltf(&path.to_string_lossy(), &ImportOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        scene.unwrap().mesh
+    }
+
+    #[test]
+    fn faces_point_outward() {
+        assert_outward(&load_tetrahedron("plain", &[[1.0, 1.0, 1.0]]));
+    }
+
+    #[test]
+    fn mirrored_node_faces_point_outward() {
+        assert_outward(&load_tetrahedron("mirrored", &[[-1.0, 1.0, 1.0]]));
+    }
+
+    #[test]
+    fn nested_mirrors_cancel_out() {
+        assert_outward(&load_tetrahedron("nested", &[[-1.0, 1.0, 1.0], [1.0, 2.0, -1.0]]));
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+
+// RGBA8 image sampled by materials. Row 0 is the top of the image, which is where
+// UVs have their origin. Color textures are sRGB encoded and decoded in the
+// shader, data textures such as roughness and normal maps are linear.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+    pub srgb: bool,
+}
+
+impl ImageTexture {
+    // Reads a PNG or JPEG file.
+    pub fn load(path: &Path, srgb: bool) -> Result<ImageTexture, image::ImageError> {
+        let image = image::open(path)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels().map(|p| p.0).collect();
+        log::info!("Loaded {width}x{height} texture {}", path.display());
+
+        Ok(ImageTexture {
+            width,
+            height,
+            pixels,
+            srgb,
+        })
+    }
+}
+
+// Packs textures for the shader: one header of four words per texture (offset of
+// its first texel, width, height and 1 when sRGB encoded), followed by the texels
+// of every texture as packed RGBA8.
+pub fn texture_table(textures: &[ImageTexture]) -> Vec<u32> {
+    let texel_count: usize = textures.iter().map(|t| t.pixels.len()).sum();
+    let mut table = Vec::with_capacity(textures.len() * 4 + texel_count);
+    let mut offset = textures.len() as u32 * 4;
+
+    for texture in textures {
+        table.extend([offset, texture.width, texture.height, texture.srgb as u32]);
+        offset += texture.pixels.len() as u32;
+    }
+    for texture in textures {
+        table.extend(texture.pixels.iter().map(|&p| u32::from_le_bytes(p)));
+    }
+
+    table
+}
@@ -0,0 +1,284 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use crate::material::Material;
+use crate::scene::luminance;
+
+const KIND_TRIANGLE: u32 = 0;
+const KIND_POINT: u32 = 1;
+const KIND_SPOT: u32 = 2;
+const KIND_DIRECTIONAL: u32 = 3;
+const KIND_AREA: u32 = 4;
+
+// Lights that exist only in the light table. Rays never hit them, so they are
+// invisible to the camera and in reflections and only reach surfaces through
+// direct light sampling. Angles are in radians.
+#[derive(Debug, Copy, Clone)]
+pub enum LightSource {
+    Point {
+        position: [f32; 3],
+        intensity: [f32; 3],
+    },
+    // Full intensity inside `inner_angle`, fading out smoothly at `outer_angle`.
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        intensity: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    // `direction` is the way the light travels. A non-zero `angular_radius`
+    // gives a disc like the sun and with it soft shadows.
+    Directional {
+        direction: [f32; 3],
+        irradiance: [f32; 3],
+        angular_radius: f32,
+    },
+    // One-sided rectangle emitting towards `edge_u` x `edge_v`.
+    Area {
+        corner: [f32; 3],
+        edge_u: [f32; 3],
+        edge_v: [f32; 3],
+        radiance: [f32; 3],
+    },
+}
+
+// Entry of the table the shader samples lights from. `cdf` is the running sum of
+// `power` over the table, so the last entry holds the total.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Light {
+    kind: u32,
+    tri: u32,
+    cdf: f32,
+    power: f32,
+    position: [f32; 3],
+    cos_outer: f32,
+    direction: [f32; 3],
+    cos_inner: f32,
+    edge_u: [f32; 3],
+    area: f32,
+    edge_v: [f32; 3],
+    _pad0: f32,
+    emission: [f32; 3],
+    _pad1: f32,
+}
+
+fn normalized(v: [f32; 3]) -> [f32; 3] {
+    Vector3::from(v).normalize().into()
+}
+
+impl LightSource {
+    // Powers only steer which light gets sampled, so they just have to be
+    // roughly comparable. Directional lights are weighted by the area of the scene
+    // they can reach.
+    fn power(&self, scene_radius: f32) -> f32 {
+        match *self {
+            LightSource::Point { intensity, .. } => luminance(intensity) * 4.0 * PI,
+            LightSource::Spot { intensity, outer_angle, .. } => {
+                luminance(intensity) * 2.0 * PI * (1.0 - outer_angle.cos())
+            }
+            LightSource::Directional { irradiance, .. } => {
+                luminance(irradiance) * PI * scene_radius * scene_radius
+            }
+            LightSource::Area { edge_u, edge_v, radiance, .. } => {
+                let area = Vector3::from(edge_u).cross(Vector3::from(edge_v)).magnitude();
+                luminance(radiance) * PI * area
+            }
+        }
+    }
+
+    fn to_light(self) -> Light {
+        match self {
+            LightSource::Point { position, intensity } => Light {
+                kind: KIND_POINT,
+                position,
+                emission: intensity,
+                ..Light::default()
+            },
+            LightSource::Spot { position, direction, intensity, inner_angle, outer_angle } => Light {
+                kind: KIND_SPOT,
+                position,
+                direction: normalized(direction),
+                cos_outer: outer_angle.cos(),
+                cos_inner: inner_angle.min(outer_angle).cos(),
+                emission: intensity,
+                ..Light::default()
+            },
+            LightSource::Directional { direction, irradiance, angular_radius } => Light {
+                kind: KIND_DIRECTIONAL,
+                direction: normalized(direction),
+                cos_outer: angular_radius.cos(),
+                emission: irradiance,
+                ..Light::default()
+            },
+            LightSource::Area { corner, edge_u, edge_v, radiance } => Light {
+                kind: KIND_AREA,
+                position: corner,
+                edge_u,
+                edge_v,
+                area: Vector3::from(edge_u).cross(Vector3::from(edge_v)).magnitude(),
+                emission: radiance,
+                ..Light::default()
+            },
+        }
+    }
+}
+
+// Builds the light table from the emissive triangles and the analytic lights.
+// Triangle indices refer to `tris` as passed in, so call this after the BVH has
+// reordered them.
+pub fn build_light_table(
+    vertices: &[[f32; 4]],
+    tris: &[[u32; 4]],
+    materials: &[Material],
+    sources: &[LightSource],
+) -> Vec<Light> {
+    let mut lights = Vec::new();
+
+    for (index, tri) in tris.iter().enumerate() {
+        let emission = materials[tri[3] as usize].emission();
+        if luminance(emission) <= 0.0 {
+            continue;
+        }
+
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vector4::from(vertices[i as usize]).truncate());
+        let area = (b - a).cross(c - a).magnitude() * 0.5;
+        if area <= 0.0 {
+            continue;
+        }
+
+        // Emissive triangles shine from both sides. The shader recomputes this
+        // power for triangles that BSDF samples hit, so the formula must match
+        // `light_pdf` in compute.wgsl.
+        lights.push(Light {
+            kind: KIND_TRIANGLE,
+            tri: index as u32,
+            power: 2.0 * PI * luminance(emission) * area,
+            area,
+            ..Light::default()
+        });
+    }
+
+    let scene_radius = scene_radius(vertices);
+    for source in sources {
+        let power = source.power(scene_radius);
+        if power <= 0.0 {
+            continue;
+        }
+        lights.push(Light {
+            power,
+            ..source.to_light()
+        });
+    }
+
+    let mut total_power = 0.0;
+    for light in &mut lights {
+        total_power += light.power;
+        light.cdf = total_power;
+    }
+    lights
+}
+
+fn scene_radius(vertices: &[[f32; 4]]) -> f32 {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for v in vertices {
+        min = std::array::from_fn(|axis| min[axis].min(v[axis]));
+        max = std::array::from_fn(|axis| max[axis].max(v[axis]));
+    }
+    if min[0] > max[0] {
+        return 1.0;
+    }
+    (Vector3::from(max) - Vector3::from(min)).magnitude().max(1e-3) * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The entry `sample_light` picks for a uniform random number `u`: the first whose
+    // running sum reaches `u` times the total.
+    fn pick(lights: &[Light], u: f32) -> usize {
+        let target = u * lights[lights.len() - 1].cdf;
+        lights.partition_point(|light| light.cdf < target)
+    }
+
+    fn table() -> Vec<Light> {
+        let vertices = [
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0, 1.0],
+            [0.0, 0.0, 1.0, 1.0],
+            [2.0, 0.0, 0.0, 1.0],
+        ];
+        let materials = [Material::diffuse([0.5; 3]), Material::emissive([4.0; 3]).unwrap()];
+        let tris = [
+            [0, 1, 2, 1],
+            [0, 2, 3, 1],
+            // Not emissive.
+            [0, 1, 3, 0],
+            // Emissive but without area.
+            [0, 1, 4, 1],
+        ];
+        let sources = [
+            LightSource::Point { position: [0.0, -2.0, 0.0], intensity: [0.0; 3] },
+            LightSource::Point { position: [0.0, -2.0, 0.0], intensity: [10.0; 3] },
+            LightSource::Spot {
+                position: [1.0, -2.0, 0.0],
+                direction: [0.0, 1.0, 0.0],
+                intensity: [20.0, 10.0, 5.0],
+                inner_angle: 0.3,
+                outer_angle: 0.5,
+            },
+            LightSource::Directional { direction: [0.0, 1.0, 0.0], irradiance: [2.0; 3], angular_radius: 0.01 },
+            LightSource::Area {
+                corner: [0.0, -3.0, 0.0],
+                edge_u: [1.0, 0.0, 0.0],
+                edge_v: [0.0, 0.0, 1.0],
+                radiance: [0.0; 3],
+            },
+        ];
+        build_light_table(&vertices, &tris, &materials, &sources)
+    }
+
+    #[test]
+    fn only_lights_with_power_enter_the_table() {
+        let lights = table();
+        let kinds: Vec<u32> = lights.iter().map(|light| light.kind).collect();
+        assert_eq!(kinds, [KIND_TRIANGLE, KIND_TRIANGLE, KIND_POINT, KIND_SPOT, KIND_DIRECTIONAL]);
+        assert_eq!([lights[0].tri, lights[1].tri], [0, 1]);
+        assert!(lights.iter().all(|light| light.power > 0.0), "{lights:?}");
+    }
+
+    #[test]
+    fn cdf_is_the_running_sum_of_power() {
+        let lights = table();
+        let total: f32 = lights.iter().map(|light| light.power).sum();
+        for pair in lights.windows(2) {
+            assert!(pair[1].cdf > pair[0].cdf, "{pair:?}");
+        }
+        let last = lights[lights.len() - 1].cdf;
+        assert!((last / total - 1.0).abs() < 1e-6, "cdf ends at {last} of {total}");
+
+        let pdf_sum: f32 = lights.iter().map(|light| light.power / last).sum();
+        assert!((pdf_sum - 1.0).abs() < 1e-6, "{pdf_sum}");
+    }
+
+    #[test]
+    fn picks_follow_power() {
+        let lights = table();
+        let total = lights[lights.len() - 1].cdf;
+        let samples = 100_000;
+        let mut counts = vec![0; lights.len()];
+        for i in 0..=samples {
+            counts[pick(&lights, i as f32 / samples as f32)] += 1;
+        }
+        for (light, count) in lights.iter().zip(counts) {
+            let expected = light.power / total;
+            let picked = count as f32 / samples as f32;
+            assert!((picked - expected).abs() < 1e-3, "{light:?} picked {picked}, expected {expected}");
+        }
+    }
+}
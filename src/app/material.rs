@@ -0,0 +1,300 @@
+use std::fmt;
+
+// Selects the BSDF the shader evaluates; stored in the GPU layout as a u32.
+#[repr(u32)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum MaterialKind {
+    #[default]
+    Diffuse = 0,
+    Metal = 1,
+    Glass = 2,
+    Principled = 3,
+}
+
+// Material parameters that can come from a texture. Textures multiply albedo,
+// roughness (green channel) and metallic (blue channel, principled only), as in
+// glTF's metallic-roughness textures. Normal maps are in tangent space with +Y up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureSlot {
+    Albedo = 0,
+    Roughness = 1,
+    Metallic = 2,
+    Normal = 3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaterialError {
+    // A parameter that has to lie in 0..=1, such as roughness.
+    OutOfRange { parameter: &'static str, value: f32 },
+    Negative { parameter: &'static str, value: f32 },
+    // Refracting materials need an index of refraction above 1.
+    Ior(f32),
+}
+
+impl fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaterialError::OutOfRange { parameter, value } => {
+                write!(f, "{parameter} must be between 0 and 1, got {value}")
+            }
+            MaterialError::Negative { parameter, value } => {
+                write!(f, "{parameter} must not be negative, got {value}")
+            }
+            MaterialError::Ior(ior) => write!(f, "index of refraction must be greater than 1, got {ior}"),
+        }
+    }
+}
+
+impl std::error::Error for MaterialError {}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    // Glass interprets this as an absorption coefficient, principled materials as
+    // the base color.
+    albedo: [f32; 3],
+    // A `MaterialKind`.
+    kind: u32,
+    emission_and_roughness: [f32; 4],
+    // x: index of refraction for glass. Metals with a complex IOR keep eta in xyz.
+    ior: [f32; 4],
+    // xyz: extinction coefficient k, w: 1 when the metal uses the complex IOR
+    // instead of Schlick's approximation with albedo as the reflectance at normal incidence.
+    extinction: [f32; 4],
+    // Principled only. x: metallic, y: specular, z: transmission, w: clearcoat.
+    principled: [f32; 4],
+    // Principled only. x: sheen, y: sheen tint, z: clearcoat roughness.
+    sheen_and_clearcoat: [f32; 4],
+    // Texture index plus one per `TextureSlot`, 0 when untextured.
+    textures: [u32; 4],
+}
+
+// Parameters of the principled BSDF, named and ranged like Blender's Principled
+// BSDF node. A specular of 0.5 is a dielectric reflectance of 4% at normal incidence.
+#[derive(Debug, Copy, Clone)]
+pub struct Principled {
+    pub base_color: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub specular: f32,
+    pub transmission: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub ior: f32,
+    pub emission: [f32; 3],
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: [0.8; 3],
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            transmission: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            ior: 1.5,
+            emission: [0.0; 3],
+        }
+    }
+}
+
+fn unit(parameter: &'static str, value: f32) -> Result<f32, MaterialError> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(MaterialError::OutOfRange { parameter, value })
+    }
+}
+
+fn non_negative(parameter: &'static str, value: f32) -> Result<f32, MaterialError> {
+    if value >= 0.0 {
+        Ok(value)
+    } else {
+        Err(MaterialError::Negative { parameter, value })
+    }
+}
+
+fn refractive(ior: f32) -> Result<f32, MaterialError> {
+    if ior > 1.0 {
+        Ok(ior)
+    } else {
+        Err(MaterialError::Ior(ior))
+    }
+}
+
+impl Material {
+    fn with_kind(kind: MaterialKind, albedo: [f32; 3]) -> Self {
+        Material {
+            albedo,
+            kind: kind as u32,
+            ..Material::default()
+        }
+    }
+
+    pub fn diffuse(albedo: [f32; 3]) -> Self {
+        Material::with_kind(MaterialKind::Diffuse, albedo)
+    }
+
+    // Black diffuse surface that only emits, as used for quad lights.
+    pub fn emissive(emission: [f32; 3]) -> Result<Self, MaterialError> {
+        Material::diffuse([0.0; 3]).with_emission(emission)
+    }
+
+    // `albedo` is the reflectance at normal incidence, used with Schlick's
+    // approximation unless `with_complex_ior` replaces it.
+    pub fn metal(albedo: [f32; 3], roughness: f32) -> Result<Self, MaterialError> {
+        let mut material = Material::with_kind(MaterialKind::Metal, albedo);
+        material.emission_and_roughness[3] = unit("roughness", roughness)?;
+        Ok(material)
+    }
+
+    // `absorption` is the fraction of light absorbed per unit distance inside.
+    pub fn glass(absorption: [f32; 3], roughness: f32, ior: f32) -> Result<Self, MaterialError> {
+        let mut material = Material::with_kind(MaterialKind::Glass, absorption);
+        material.emission_and_roughness[3] = unit("roughness", roughness)?;
+        material.ior[0] = refractive(ior)?;
+        Ok(material)
+    }
+
+    // The IOR only matters with transmission, so it is only checked then.
+    pub fn principled(params: &Principled) -> Result<Self, MaterialError> {
+        let mut material =
+            Material::with_kind(MaterialKind::Principled, params.base_color).with_emission(params.emission)?;
+        material.emission_and_roughness[3] = unit("roughness", params.roughness)?;
+        material.ior[0] = if params.transmission > 0.0 { refractive(params.ior)? } else { params.ior };
+        material.principled = [
+            unit("metallic", params.metallic)?,
+            non_negative("specular", params.specular)?,
+            unit("transmission", params.transmission)?,
+            unit("clearcoat", params.clearcoat)?,
+        ];
+        material.sheen_and_clearcoat = [
+            non_negative("sheen", params.sheen)?,
+            unit("sheen tint", params.sheen_tint)?,
+            unit("clearcoat roughness", params.clearcoat_roughness)?,
+            0.0,
+        ];
+        Ok(material)
+    }
+
+    pub fn with_emission(mut self, emission: [f32; 3]) -> Result<Self, MaterialError> {
+        for value in emission {
+            non_negative("emission", value)?;
+        }
+        self.emission_and_roughness[..3].copy_from_slice(&emission);
+        Ok(self)
+    }
+
+    // Per channel Fresnel for metals, as in measured data such as refractiveindex.info.
+    pub fn with_complex_ior(mut self, eta: [f32; 3], k: [f32; 3]) -> Self {
+        self.ior = [eta[0], eta[1], eta[2], 0.0];
+        self.extinction = [k[0], k[1], k[2], 1.0];
+        self
+    }
+
+    // `index` refers to the textures of the mesh or scene holding the material.
+    pub fn with_texture(mut self, slot: TextureSlot, index: u32) -> Self {
+        self.textures[slot as usize] = index + 1;
+        self
+    }
+
+    // Re-bases texture indices when the material's textures are appended behind
+    // `offset` others.
+    pub(crate) fn offset_textures(&mut self, offset: u32) {
+        for texture in &mut self.textures {
+            if *texture > 0 {
+                *texture += offset;
+            }
+        }
+    }
+
+    pub fn kind(&self) -> MaterialKind {
+        match self.kind {
+            1 => MaterialKind::Metal,
+            2 => MaterialKind::Glass,
+            3 => MaterialKind::Principled,
+            _ => MaterialKind::Diffuse,
+        }
+    }
+
+    pub fn emission(&self) -> [f32; 3] {
+        [
+            self.emission_and_roughness[0],
+            self.emission_and_roughness[1],
+            self.emission_and_roughness[2],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_parameters_are_range_checked() {
+        assert!(Material::metal([0.5; 3], 0.0).is_ok());
+        assert!(Material::metal([0.5; 3], 1.0).is_ok());
+        assert_eq!(
+            Material::metal([0.5; 3], 1.5).unwrap_err(),
+            MaterialError::OutOfRange { parameter: "roughness", value: 1.5 }
+        );
+        assert_eq!(
+            Material::glass([0.0; 3], -0.1, 1.5).unwrap_err(),
+            MaterialError::OutOfRange { parameter: "roughness", value: -0.1 }
+        );
+        assert!(matches!(
+            Material::metal([0.5; 3], f32::NAN),
+            Err(MaterialError::OutOfRange { parameter: "roughness", .. })
+        ));
+
+        let principled = |params: Principled| Material::principled(&params).unwrap_err();
+        assert_eq!(
+            principled(Principled { metallic: 2.0, ..Default::default() }),
+            MaterialError::OutOfRange { parameter: "metallic", value: 2.0 }
+        );
+        assert_eq!(
+            principled(Principled { clearcoat_roughness: -1.0, ..Default::default() }),
+            MaterialError::OutOfRange { parameter: "clearcoat roughness", value: -1.0 }
+        );
+        assert_eq!(
+            principled(Principled { specular: -0.5, ..Default::default() }),
+            MaterialError::Negative { parameter: "specular", value: -0.5 }
+        );
+    }
+
+    #[test]
+    fn refraction_needs_an_ior_above_one() {
+        assert_eq!(Material::glass([0.0; 3], 0.0, 1.0).unwrap_err(), MaterialError::Ior(1.0));
+        assert!(matches!(Material::glass([0.0; 3], 0.0, f32::NAN), Err(MaterialError::Ior(_))));
+
+        let params = Principled { ior: 0.9, ..Default::default() };
+        assert!(Material::principled(&params).is_ok());
+        assert_eq!(
+            Material::principled(&Principled { transmission: 0.5, ..params }).unwrap_err(),
+            MaterialError::Ior(0.9)
+        );
+    }
+
+    #[test]
+    fn emission_must_not_be_negative() {
+        assert_eq!(Material::emissive([1.0, 2.0, 3.0]).unwrap().emission(), [1.0, 2.0, 3.0]);
+        assert_eq!(
+            Material::emissive([1.0, -2.0, 3.0]).unwrap_err(),
+            MaterialError::Negative { parameter: "emission", value: -2.0 }
+        );
+        assert!(matches!(
+            Material::diffuse([0.5; 3]).with_emission([0.0, 0.0, f32::NAN]),
+            Err(MaterialError::Negative { parameter: "emission", .. })
+        ));
+        assert_eq!(
+            Material::principled(&Principled { emission: [-1.0; 3], ..Default::default() }).unwrap_err(),
+            MaterialError::Negative { parameter: "emission", value: -1.0 }
+        );
+    }
+}
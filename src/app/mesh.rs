@@ -1,15 +1,557 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
-pub fn parse_obj(path: &str) -> (Vec<[f32; 4]>, Vec<[u32; 4]>) {
-    let file = File::open(path).expect("OBJ load failed");
+use cgmath::{InnerSpace, Vector3};
+
+use crate::image_texture::ImageTexture;
+use crate::material::{Material, MaterialError, Principled, TextureSlot};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    Mtl { path: String, source: Box<ObjError> },
+    Material { name: String, source: MaterialError },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "failed to read OBJ: {err}"),
+            ObjError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            ObjError::Mtl { path, source } => write!(f, "in material library {path}: {source}"),
+            ObjError::Material { name, source } => write!(f, "material `{name}`: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<std::io::Error> for ObjError {
+    fn from(err: std::io::Error) -> Self {
+        ObjError::Io(err)
+    }
+}
+
+// Vertices are de-indexed so that `vertices`, `normals` and `uvs` share one index.
+// Corners without a `vn` or `vt` reference get a zero normal or uv.
+// UVs have their origin at the top left of the image, as in glTF.
+// `materials` is empty when the file assigns none, in which case every triangle
+// uses material index 0 and the caller chooses what that is. Material texture
+// indices refer to `textures`.
+#[derive(Default, Debug)]
+pub struct Mesh {
+    pub vertices: Vec<[f32; 4]>,
+    pub normals: Vec<[f32; 4]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub triangles: Vec<[u32; 4]>,
+    pub materials: Vec<Material>,
+    pub material_names: Vec<String>,
+    pub textures: Vec<ImageTexture>,
+}
+
+impl Mesh {
+    // Gives vertices without a normal the area weighted average of the faces around
+    // their position, leaving out faces at more than `crease_angle` degrees to the
+    // one being shaded. Vertices shared across a crease are split.
+    pub fn fill_missing_normals(&mut self, crease_angle: f32) {
+        let missing: Vec<bool> = self.normals.iter().map(|n| n[0] == 0.0 && n[1] == 0.0 && n[2] == 0.0).collect();
+        if !missing.contains(&true) {
+            return;
+        }
+
+        let position = |v: [f32; 4]| Vector3::new(v[0], v[1], v[2]);
+        let key = |v: [f32; 4]| [v[0].to_bits(), v[1].to_bits(), v[2].to_bits()];
+        // Same orientation as the face normal the shader computes, outward for
+        // counter-clockwise faces; the length is twice the area.
+        let face_normals: Vec<Vector3<f32>> = self
+            .triangles
+            .iter()
+            .map(|tri| {
+                let p0 = position(self.vertices[tri[0] as usize]);
+                let e1 = position(self.vertices[tri[1] as usize]) - p0;
+                let e2 = position(self.vertices[tri[2] as usize]) - p0;
+                e1.cross(e2)
+            })
+            .collect();
+
+        let mut faces_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (face, tri) in self.triangles.iter().enumerate() {
+            for &vertex in &tri[..3] {
+                faces_at.entry(key(self.vertices[vertex as usize])).or_default().push(face);
+            }
+        }
+
+        let cos_crease = crease_angle.to_radians().cos();
+        let mut assigned: HashMap<u32, [u32; 3]> = HashMap::new();
+        let mut splits: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        for face in 0..self.triangles.len() {
+            if face_normals[face].magnitude2() == 0.0 {
+                continue;
+            }
+            let own = face_normals[face].normalize();
+
+            for corner in 0..3 {
+                let vertex = self.triangles[face][corner];
+                if !missing[vertex as usize] {
+                    continue;
+                }
+
+                let sum: Vector3<f32> = faces_at[&key(self.vertices[vertex as usize])]
+                    .iter()
+                    .map(|&other| face_normals[other])
+                    .filter(|n| n.magnitude2() > 0.0 && n.normalize().dot(own) >= cos_crease)
+                    .sum();
+                let n = sum.normalize();
+                let normal = [n.x, n.y, n.z, 0.0];
+                let bits = [n.x.to_bits(), n.y.to_bits(), n.z.to_bits()];
+
+                match assigned.get(&vertex) {
+                    None => {
+                        self.normals[vertex as usize] = normal;
+                        assigned.insert(vertex, bits);
+                    }
+                    Some(&existing) if existing == bits => {}
+                    Some(_) => {
+                        let split = *splits.entry((vertex, bits)).or_insert_with(|| {
+                            self.vertices.push(self.vertices[vertex as usize]);
+                            self.normals.push(normal);
+                            self.uvs.push(self.uvs[vertex as usize]);
+                            self.vertices.len() as u32 - 1
+                        });
+                        self.triangles[face][corner] = split;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+// Applied in order: up-axis conversion and handedness flip, recentering on the
+// bounding box, normalizing the largest extent to 1, and finally `scale`.
+// Vertices the file gives no normal are then smoothed across faces meeting at
+// less than `crease_angle` degrees.
+#[derive(Debug, Copy, Clone)]
+pub struct ImportOptions {
+    pub scale: [f32; 3],
+    pub up_axis: UpAxis,
+    pub flip_handedness: bool,
+    pub recenter: bool,
+    pub normalize: bool,
+    pub crease_angle: f32,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            scale: [1.0; 3],
+            up_axis: UpAxis::Y,
+            flip_handedness: false,
+            recenter: false,
+            normalize: false,
+            crease_angle: 30.0,
+        }
+    }
+}
+
+impl ImportOptions {
+    pub fn uniform(scale: f32) -> Self {
+        ImportOptions {
+            scale: [scale; 3],
+            ..Default::default()
+        }
+    }
+
+    // Scene space is the right-handed Y-up frame rotated half a turn around Z,
+    // so up is -Y and the default camera sees +X on the right.
+    fn convert_axes(&self, v: [f32; 3]) -> [f32; 3] {
+        let x = if self.flip_handedness { -v[0] } else { v[0] };
+        match self.up_axis {
+            UpAxis::Y => [-x, -v[1], v[2]],
+            UpAxis::Z => [-x, -v[2], -v[1]],
+        }
+    }
+
+    pub fn resolve(&self, vertices: &[[f32; 4]]) -> ImportTransform {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for v in vertices {
+            let p = self.convert_axes([v[0], v[1], v[2]]);
+            min = std::array::from_fn(|i| min[i].min(p[i]));
+            max = std::array::from_fn(|i| max[i].max(p[i]));
+        }
+
+        let mut center = [0.0; 3];
+        let mut factor = 1.0;
+        if !vertices.is_empty() {
+            if self.recenter {
+                center = std::array::from_fn(|i| (min[i] + max[i]) * 0.5);
+            }
+            let extent = (0..3).map(|i| max[i] - min[i]).fold(0.0, f32::max);
+            if self.normalize && extent > 0.0 {
+                factor = 1.0 / extent;
+            }
+        }
+
+        ImportTransform {
+            options: *self,
+            center,
+            factor,
+        }
+    }
+
+    // Transforms a mesh built in source space and returns the transform used so
+    // cameras and lights from the same file can follow it.
+    pub fn apply(&self, mesh: &mut Mesh) -> ImportTransform {
+        let transform = self.resolve(&mesh.vertices);
+
+        for v in &mut mesh.vertices {
+            let [x, y, z] = transform.point([v[0], v[1], v[2]]);
+            *v = [x, y, z, 1.0];
+        }
+        for n in &mut mesh.normals {
+            let [x, y, z] = transform.normal([n[0], n[1], n[2]]);
+            *n = [x, y, z, 0.0];
+        }
+        if transform.mirrored() {
+            for tri in &mut mesh.triangles {
+                tri.swap(1, 2);
+            }
+        }
+        mesh.fill_missing_normals(self.crease_angle);
+
+        transform
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ImportTransform {
+    options: ImportOptions,
+    center: [f32; 3],
+    factor: f32,
+}
+
+fn normalize_or_zero(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 {
+        v.map(|c| c / length)
+    } else {
+        v
+    }
+}
+
+impl ImportTransform {
+    pub fn point(&self, p: [f32; 3]) -> [f32; 3] {
+        let p = self.options.convert_axes(p);
+        std::array::from_fn(|i| (p[i] - self.center[i]) * self.factor * self.options.scale[i])
+    }
+
+    pub fn direction(&self, d: [f32; 3]) -> [f32; 3] {
+        let d = self.options.convert_axes(d);
+        normalize_or_zero(std::array::from_fn(|i| d[i] * self.options.scale[i]))
+    }
+
+    pub fn normal(&self, n: [f32; 3]) -> [f32; 3] {
+        let n = self.options.convert_axes(n);
+        normalize_or_zero(std::array::from_fn(|i| n[i] / self.options.scale[i]))
+    }
+
+    // True when the transform mirrors geometry, so triangle winding must be reversed.
+    pub fn mirrored(&self) -> bool {
+        let [x, y, z] = self.options.scale;
+        self.options.flip_handedness != (x * y * z < 0.0)
+    }
+}
+
+fn parse_floats<const N: usize>(parts: &[&str], line: usize) -> Result<[f32; N], ObjError> {
+    if parts.len() < N {
+        return Err(ObjError::Parse {
+            line,
+            message: format!("expected {N} components, found {}", parts.len()),
+        });
+    }
+
+    let mut values = [0.0; N];
+    for (value, part) in values.iter_mut().zip(parts) {
+        *value = part.parse().map_err(|_| ObjError::Parse {
+            line,
+            message: format!("invalid number `{part}`"),
+        })?;
+    }
+    Ok(values)
+}
+
+// OBJ indices are 1-based, and negative indices count back from the last element read.
+fn resolve_index(token: &str, len: usize, kind: &str, line: usize) -> Result<u32, ObjError> {
+    let index: i64 = token.parse().map_err(|_| ObjError::Parse {
+        line,
+        message: format!("invalid {kind} index `{token}`"),
+    })?;
+
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i if i < 0 => len as i64 + i,
+        _ => {
+            return Err(ObjError::Parse {
+                line,
+                message: format!("{kind} index 0 is not valid"),
+            })
+        }
+    };
+
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(ObjError::Parse {
+            line,
+            message: format!("{kind} index {index} is out of range ({len} defined)"),
+        });
+    }
+    Ok(resolved as u32)
+}
+
+struct MtlRecord {
+    kd: [f32; 3],
+    ks: [f32; 3],
+    ke: [f32; 3],
+    tf: Option<[f32; 3]>,
+    ns: f32,
+    ni: f32,
+    d: f32,
+    illum: u32,
+    // PBR extension (Pr, Pm, Ps, Pc, Pcr), as written by Blender's exporter.
+    pbr: MtlPbr,
+    // Texture per `TextureSlot`, resolved against the library's directory.
+    maps: [Option<PathBuf>; 4],
+}
+
+#[derive(Default)]
+struct MtlPbr {
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    sheen: Option<f32>,
+    clearcoat: Option<f32>,
+    clearcoat_roughness: Option<f32>,
+}
+
+impl MtlPbr {
+    fn is_set(&self) -> bool {
+        self.roughness.is_some()
+            || self.metallic.is_some()
+            || self.sheen.is_some()
+            || self.clearcoat.is_some()
+            || self.clearcoat_roughness.is_some()
+    }
+}
+
+impl Default for MtlRecord {
+    fn default() -> Self {
+        MtlRecord {
+            kd: [0.8; 3],
+            ks: [0.0; 3],
+            ke: [0.0; 3],
+            tf: None,
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            illum: 2,
+            pbr: MtlPbr::default(),
+            maps: Default::default(),
+        }
+    }
+}
+
+impl MtlRecord {
+    // Records with PBR extension statements become principled materials. Otherwise
+    // illum 3/5 are mirror models and 4/6/7/9 are transparent ones; anything that
+    // is not fully opaque is treated as glass as well.
+    fn to_material(&self) -> Result<Material, MaterialError> {
+        // Inverse of Blender's exporter, which writes Ns = (1 - roughness)^2 * 1000.
+        let mut roughness = (1.0 - (self.ns.max(0.0) / 1000.0).sqrt()).clamp(0.0, 1.0);
+        let ior = if self.ni > 1.0 { self.ni } else { 1.5 };
+        // Roughness and metallic maps replace the constants rather than scaling them.
+        let roughness_map = self.maps[TextureSlot::Roughness as usize].is_some();
+        let metallic_map = self.maps[TextureSlot::Metallic as usize].is_some();
+        if roughness_map {
+            roughness = 1.0;
+        }
+
+        if self.pbr.is_set() || metallic_map {
+            let defaults = Principled::default();
+            let transparent = matches!(self.illum, 4 | 6 | 7 | 9) || self.d < 1.0;
+            return Material::principled(&Principled {
+                base_color: self.kd,
+                metallic: if metallic_map { 1.0 } else { self.pbr.metallic.unwrap_or(defaults.metallic) },
+                roughness: if roughness_map { 1.0 } else { self.pbr.roughness.unwrap_or(roughness) },
+                transmission: if transparent { 1.0 - self.d.clamp(0.0, 1.0) } else { 0.0 },
+                clearcoat: self.pbr.clearcoat.unwrap_or(defaults.clearcoat),
+                clearcoat_roughness: self.pbr.clearcoat_roughness.unwrap_or(defaults.clearcoat_roughness),
+                sheen: self.pbr.sheen.unwrap_or(defaults.sheen),
+                ior,
+                emission: self.ke,
+                ..defaults
+            });
+        }
+
+        if matches!(self.illum, 4 | 6 | 7 | 9) || self.d < 1.0 {
+            // Glass albedo is an absorption coefficient in the shader.
+            let transmission = self.tf.unwrap_or(self.kd);
+            let absorption = transmission.map(|c| (1.0 - c).clamp(0.0, 1.0));
+            Material::glass(absorption, roughness, ior)?.with_emission(self.ke)
+        } else if matches!(self.illum, 3 | 5) {
+            let albedo = if self.ks.iter().any(|&c| c > 0.0) { self.ks } else { self.kd };
+            Material::metal(albedo, roughness)?.with_emission(self.ke)
+        } else {
+            Material::diffuse(self.kd).with_emission(self.ke)
+        }
+    }
+
+    fn into_named_material(self, name: String, textures: &mut TextureCache) -> Result<(String, Material), ObjError> {
+        let mut material = match self.to_material() {
+            Ok(material) => material,
+            Err(source) => return Err(ObjError::Material { name, source }),
+        };
+
+        let slots = [TextureSlot::Albedo, TextureSlot::Roughness, TextureSlot::Metallic, TextureSlot::Normal];
+        for (slot, map) in slots.into_iter().zip(&self.maps) {
+            if let Some(index) = map.as_deref().and_then(|path| textures.get(path, slot == TextureSlot::Albedo)) {
+                material = material.with_texture(slot, index);
+            }
+        }
+        Ok((name, material))
+    }
+}
+
+// Textures referenced by material libraries, loaded once per file and color space.
+// Files that fail to load are skipped with a warning, like missing libraries.
+#[derive(Default)]
+struct TextureCache {
+    textures: Vec<ImageTexture>,
+    slots: HashMap<(PathBuf, bool), Option<u32>>,
+}
+
+impl TextureCache {
+    fn get(&mut self, path: &Path, srgb: bool) -> Option<u32> {
+        if let Some(&slot) = self.slots.get(&(path.to_path_buf(), srgb)) {
+            return slot;
+        }
+
+        let slot = match ImageTexture::load(path, srgb) {
+            Ok(texture) => {
+                self.textures.push(texture);
+                Some(self.textures.len() as u32 - 1)
+            }
+            Err(err) => {
+                log::warn!("Skipping texture {}: {err}", path.display());
+                None
+            }
+        };
+        self.slots.insert((path.to_path_buf(), srgb), slot);
+        slot
+    }
+}
+
+fn parse_mtl(path: &Path, textures: &mut TextureCache) -> Result<Vec<(String, Material)>, ObjError> {
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
+    let base_dir = path.parent().unwrap_or(Path::new(""));
 
-    let mut vertices: Vec<[f32; 4]> = Vec::new();
-    let mut triangles: Vec<[u32; 4]> = Vec::new();
+    let mut materials = Vec::new();
+    let mut current: Option<(String, MtlRecord)> = None;
 
-    for line in reader.lines() {
-        let line = line.unwrap();
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = line_index + 1;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+
+        if parts[0] == "newmtl" {
+            if let Some((name, record)) = current.take() {
+                materials.push(record.into_named_material(name, textures)?);
+            }
+            current = Some((parts[1..].join(" "), MtlRecord::default()));
+            continue;
+        }
+
+        let Some((_, record)) = current.as_mut() else {
+            continue;
+        };
+
+        match parts[0] {
+            "Kd" => record.kd = parse_floats(&parts[1..], line_number)?,
+            "Ks" => record.ks = parse_floats(&parts[1..], line_number)?,
+            "Ke" => record.ke = parse_floats(&parts[1..], line_number)?,
+            "Tf" => record.tf = Some(parse_floats(&parts[1..], line_number)?),
+            "Ns" => record.ns = parse_floats::<1>(&parts[1..], line_number)?[0],
+            "Ni" => record.ni = parse_floats::<1>(&parts[1..], line_number)?[0],
+            "d" => record.d = parse_floats::<1>(&parts[1..], line_number)?[0],
+            "Tr" => record.d = 1.0 - parse_floats::<1>(&parts[1..], line_number)?[0],
+            "Pr" => record.pbr.roughness = Some(parse_floats::<1>(&parts[1..], line_number)?[0]),
+            "Pm" => record.pbr.metallic = Some(parse_floats::<1>(&parts[1..], line_number)?[0]),
+            "Ps" => record.pbr.sheen = Some(parse_floats::<1>(&parts[1..], line_number)?[0]),
+            "Pc" => record.pbr.clearcoat = Some(parse_floats::<1>(&parts[1..], line_number)?[0]),
+            "Pcr" => record.pbr.clearcoat_roughness = Some(parse_floats::<1>(&parts[1..], line_number)?[0]),
+            // Options such as `-bm 1.0` come before the file name, so it is the last token.
+            "map_Kd" | "map_Pr" | "map_Pm" | "norm" | "map_Bump" | "bump" => {
+                let slot = match parts[0] {
+                    "map_Kd" => TextureSlot::Albedo,
+                    "map_Pr" => TextureSlot::Roughness,
+                    "map_Pm" => TextureSlot::Metallic,
+                    // Blender's exporter writes normal maps as map_Bump.
+                    _ => TextureSlot::Normal,
+                };
+                let file = parts.last().filter(|_| parts.len() > 1).ok_or_else(|| ObjError::Parse {
+                    line: line_number,
+                    message: format!("{} needs a file name", parts[0]),
+                })?;
+                record.maps[slot as usize] = Some(base_dir.join(file));
+            }
+            "illum" => {
+                record.illum = parts.get(1).and_then(|p| p.parse().ok()).ok_or_else(|| ObjError::Parse {
+                    line: line_number,
+                    message: "invalid illumination model".to_string(),
+                })?
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((name, record)) = current {
+        materials.push(record.into_named_material(name, textures)?);
+    }
+
+    Ok(materials)
+}
+
+pub fn parse_obj(path: &str, options: &ImportOptions) -> Result<Mesh, ObjError> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut corner_map: HashMap<(u32, Option<u32>, Option<u32>), u32> = HashMap::new();
+    let mut mesh = Mesh::default();
+
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut library: HashMap<String, Material> = HashMap::new();
+    let mut textures = TextureCache::default();
+    let mut material_slots: HashMap<String, u32> = HashMap::new();
+    let mut fallback_slot: Option<u32> = None;
+    let mut current_material: Option<u32> = None;
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = line_index + 1;
         let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.is_empty() {
             continue;
@@ -17,25 +559,429 @@ pub fn parse_obj(path: &str) -> (Vec<[f32; 4]>, Vec<[u32; 4]>) {
 
         match parts[0] {
             "v" => {
-                let x: f32 = parts[1].parse().unwrap();
-                let y: f32 = parts[2].parse().unwrap();
-                let z: f32 = parts[3].parse().unwrap();
-                vertices.push([x * 10.0, -y * 10.0, z * 10.0, 1.0]);
+                positions.push(parse_floats(&parts[1..], line_number)?);
+            }
+            "vn" => {
+                normals.push(parse_floats(&parts[1..], line_number)?);
+            }
+            "vt" => {
+                let [u] = parse_floats(&parts[1..], line_number)?;
+                let v = match parts.get(2) {
+                    Some(_) => parse_floats::<1>(&parts[2..], line_number)?[0],
+                    None => 0.0,
+                };
+                // OBJ's v runs up the image.
+                uvs.push([u, 1.0 - v]);
+            }
+            "mtllib" => {
+                for name in &parts[1..] {
+                    let mtl_path = base_dir.join(name);
+                    match parse_mtl(&mtl_path, &mut textures) {
+                        Ok(materials) => library.extend(materials),
+                        Err(ObjError::Io(err)) => {
+                            log::warn!("Skipping material library {}: {err}", mtl_path.display());
+                        }
+                        Err(err) => {
+                            return Err(ObjError::Mtl {
+                                path: mtl_path.display().to_string(),
+                                source: Box::new(err),
+                            })
+                        }
+                    }
+                }
+            }
+            "usemtl" => {
+                let name = parts[1..].join(" ");
+                current_material = Some(match material_slots.get(&name) {
+                    Some(&slot) => slot,
+                    None => match library.get(&name) {
+                        Some(material) => {
+                            mesh.materials.push(*material);
+                            mesh.material_names.push(name.clone());
+                            let slot = mesh.materials.len() as u32 - 1;
+                            material_slots.insert(name, slot);
+                            slot
+                        }
+                        None => {
+                            log::warn!("line {line_number}: unknown material `{name}`, using the default");
+                            *fallback_slot.get_or_insert_with(|| push_default_material(&mut mesh))
+                        }
+                    },
+                });
             }
             "f" => {
-                let indices: Vec<u32> = parts[1..]
-                .iter()
-                .map(|s| s.split('/').next().unwrap().parse::<u32>().unwrap() - 1)
-                .collect();
-            
+                if parts.len() < 4 {
+                    return Err(ObjError::Parse {
+                        line: line_number,
+                        message: format!("face needs at least 3 vertices, found {}", parts.len() - 1),
+                    });
+                }
+
+                let mut indices: Vec<u32> = Vec::with_capacity(parts.len() - 1);
+                for corner in &parts[1..] {
+                    let mut fields = corner.split('/');
+                    let position = resolve_index(fields.next().unwrap_or(""), positions.len(), "vertex", line_number)?;
+                    let uv = match fields.next() {
+                        Some("") | None => None,
+                        Some(token) => Some(resolve_index(token, uvs.len(), "texture coordinate", line_number)?),
+                    };
+                    let normal = match fields.next() {
+                        Some("") | None => None,
+                        Some(token) => Some(resolve_index(token, normals.len(), "normal", line_number)?),
+                    };
+
+                    let index = *corner_map.entry((position, uv, normal)).or_insert_with(|| {
+                        let [x, y, z] = positions[position as usize];
+                        mesh.vertices.push([x, y, z, 1.0]);
+                        let [nx, ny, nz] = normal.map_or([0.0; 3], |n| normals[n as usize]);
+                        mesh.normals.push([nx, ny, nz, 0.0]);
+                        mesh.uvs.push(uv.map_or([0.0; 2], |t| uvs[t as usize]));
+                        mesh.vertices.len() as u32 - 1
+                    });
+                    indices.push(index);
+                }
+
+                let material = match current_material {
+                    Some(slot) => slot,
+                    None => *fallback_slot.get_or_insert_with(|| push_default_material(&mut mesh)),
+                };
+
                 for i in 1..indices.len() - 1 {
-                    triangles.push([indices[0], indices[i], indices[i + 1], 0]);
+                    mesh.triangles.push([indices[0], indices[i], indices[i + 1], material]);
                 }
             }
             _ => {}
         }
     }
 
-    (vertices, triangles)
+    // Faces that never reached a known material leave the choice to the caller.
+    if material_slots.is_empty() {
+        mesh.materials.clear();
+        mesh.material_names.clear();
+        for tri in &mut mesh.triangles {
+            tri[3] = 0;
+        }
+    }
+
+    mesh.textures = textures.textures;
+    options.apply(&mut mesh);
+    Ok(mesh)
+}
+
+fn push_default_material(mesh: &mut Mesh) -> u32 {
+    mesh.materials.push(Material::diffuse(MtlRecord::default().kd));
+    mesh.material_names.push("default".to_string());
+    mesh.materials.len() as u32 - 1
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::material::MaterialKind;
+
+    // Tetrahedron with every face counter-clockwise seen from outside, as OBJ and
+    // glTF wind them.
+    pub(crate) fn tetrahedron() -> Mesh {
+        let vertices = vec![
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 1.0],
+        ];
+        Mesh {
+            normals: vec![[0.0; 4]; vertices.len()],
+            uvs: vec![[0.0; 2]; vertices.len()],
+            vertices,
+            triangles: vec![[0, 2, 1, 0], [0, 1, 3, 0], [0, 3, 2, 0], [1, 2, 3, 0]],
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn assert_outward(mesh: &Mesh) {
+        let position = |i: u32| {
+            let v = mesh.vertices[i as usize];
+            Vector3::new(v[0], v[1], v[2])
+        };
+        let center = (0..4).map(position).sum::<Vector3<f32>>() / 4.0;
+        for tri in &mesh.triangles {
+            let p0 = position(tri[0]);
+            let outward = (p0 + position(tri[1]) + position(tri[2])) / 3.0 - center;
+            // As `surface_hit` computes it.
+            let face_normal = (position(tri[1]) - p0).cross(position(tri[2]) - p0);
+            assert!(face_normal.dot(outward) > 0.0, "face {tri:?} points inward");
+            for &vertex in &tri[..3] {
+                let n = mesh.normals[vertex as usize];
+                let normal = Vector3::new(n[0], n[1], n[2]);
+                assert!(normal.dot(outward) > 0.0, "normal of vertex {vertex} in {tri:?} points inward");
+            }
+        }
+    }
+
+    // Parses `text` as an OBJ file with the default import options.
+    fn parse(name: &str, text: &str) -> Result<Mesh, ObjError> {
+        let path = std::env::temp_dir().join(format!("{name}_{}.obj", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let result = parse_obj(&path.to_string_lossy(), &ImportOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn parse_error_line(name: &str, text: &str) -> (usize, String) {
+        match parse(name, text) {
+            Err(ObjError::Parse { line, message }) => (line, message),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let text = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 0 1
+vn 0 0 1
+f -3/-3/-1 -2/-2/-1 -1/-1/-1
+v 5 5 5
+v 6 5 5
+f 1 -2 -1
+";
+        let mesh = parse("obj_negative_indices", text).unwrap();
+        let transform = ImportOptions::default().resolve(&[]);
+        let corner = |tri: usize, corner: usize| mesh.triangles[tri][corner] as usize;
+        let position = |tri: usize, i: usize| {
+            let [x, y, z, _] = mesh.vertices[corner(tri, i)];
+            [x, y, z]
+        };
+
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(position(0, 0), transform.point([0.0, 0.0, 0.0]));
+        assert_eq!(position(0, 1), transform.point([1.0, 0.0, 0.0]));
+        assert_eq!(position(0, 2), transform.point([0.0, 1.0, 0.0]));
+        assert_eq!(mesh.uvs[corner(0, 1)], [1.0, 1.0]);
+        assert_eq!(mesh.uvs[corner(0, 2)], [0.0, 0.0]);
+        let [nx, ny, nz, _] = mesh.normals[corner(0, 0)];
+        assert_eq!([nx, ny, nz], transform.normal([0.0, 0.0, 1.0]));
+
+        // Negative indices are relative to the vertices read so far.
+        assert_eq!(position(1, 0), transform.point([0.0, 0.0, 0.0]));
+        assert_eq!(position(1, 1), transform.point([5.0, 5.0, 5.0]));
+        assert_eq!(position(1, 2), transform.point([6.0, 5.0, 5.0]));
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let (line, message) = parse_error_line("obj_bad_number", "v 0 0 0\n\n# comment\nv 1 x 0\n");
+        assert_eq!(line, 4);
+        assert!(message.contains("`x`"), "{message}");
+
+        let (line, message) = parse_error_line("obj_out_of_range", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -4\n");
+        assert_eq!(line, 4);
+        assert!(message.contains("out of range"), "{message}");
+
+        let (line, message) = parse_error_line("obj_zero_index", "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nf 1/0 2 3\n");
+        assert_eq!(line, 5);
+        assert!(message.contains("index 0"), "{message}");
+
+        let (line, _) = parse_error_line("obj_short_face", "v 0 0 0\nv 1 0 0\nf 1 2\n");
+        assert_eq!(line, 3);
+    }
+
+    // Splits quads, given counter-clockwise, into two triangles each.
+    fn quads(vertices: &[[f32; 4]], quads: &[[u32; 4]]) -> Mesh {
+        Mesh {
+            normals: vec![[0.0; 4]; vertices.len()],
+            uvs: vec![[0.0; 2]; vertices.len()],
+            vertices: vertices.to_vec(),
+            triangles: quads.iter().flat_map(|&[a, b, c, d]| [[a, b, c, 0], [a, c, d, 0]]).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn creases_split_cube_corners() {
+        // Corner i is at (i & 1, i >> 1 & 1, i >> 2 & 1).
+        let vertices: Vec<_> = (0..8)
+            .map(|i| [(i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32, 1.0])
+            .collect();
+        let faces = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+
+        for crease_angle in [30.0, 89.0] {
+            let mut mesh = quads(&vertices, &faces);
+            mesh.fill_missing_normals(crease_angle);
+
+            // Every corner is shared by three faces, each with its own normal.
+            assert_eq!(mesh.vertices.len(), 24, "crease angle {crease_angle}");
+            for tri in &mesh.triangles {
+                let position = |i: u32| {
+                    let v = mesh.vertices[i as usize];
+                    Vector3::new(v[0], v[1], v[2])
+                };
+                let p0 = position(tri[0]);
+                let face_normal = (position(tri[1]) - p0).cross(position(tri[2]) - p0).normalize();
+                for &vertex in &tri[..3] {
+                    let [x, y, z, _] = mesh.normals[vertex as usize];
+                    assert_eq!(Vector3::new(x, y, z), face_normal, "vertex {vertex} of {tri:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_grids_stay_welded() {
+        // 3 by 3 quads in the z = 0 plane.
+        let vertices: Vec<_> = (0..16).map(|i| [(i % 4) as f32, (i / 4) as f32, 0.0, 1.0]).collect();
+        let faces: Vec<[u32; 4]> = (0..9).map(|q| q / 3 * 4 + q % 3).map(|i| [i, i + 1, i + 5, i + 4]).collect();
+        let mut mesh = quads(&vertices, &faces);
+        mesh.fill_missing_normals(30.0);
+
+        assert_eq!(mesh.vertices.len(), 16);
+        assert!(mesh.normals.iter().all(|&n| n == [0.0, 0.0, 1.0, 0.0]), "{:?}", mesh.normals);
+    }
+
+    #[test]
+    fn imported_faces_point_outward() {
+        let mut mesh = tetrahedron();
+        ImportOptions::default().apply(&mut mesh);
+        assert_outward(&mesh);
+    }
+
+    #[test]
+    fn mirrored_import_faces_point_outward() {
+        let mut mesh = tetrahedron();
+        let options = ImportOptions {
+            flip_handedness: true,
+            up_axis: UpAxis::Z,
+            ..Default::default()
+        };
+        assert!(options.resolve(&mesh.vertices).mirrored());
+        options.apply(&mut mesh);
+        assert_outward(&mesh);
+    }
+
+    // Writes `text` as a material library, plus blank images at the given paths
+    // relative to it, into a directory of its own and parses it. Also returns the
+    // number of textures loaded.
+    fn parse_library(name: &str, text: &str, images: &[&str]) -> Result<(HashMap<String, Material>, usize), ObjError> {
+        let dir = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for image in images {
+            let path = dir.join(image);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image::RgbaImage::new(1, 1).save(&path).unwrap();
+        }
+        let path = dir.join("library.mtl");
+        std::fs::write(&path, text).unwrap();
+
+        let mut textures = TextureCache::default();
+        let result = parse_mtl(&path, &mut textures);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.map(|materials| (materials.into_iter().collect(), textures.textures.len()))
+    }
+
+    fn assert_same(material: &Material, expected: &Material) {
+        assert_eq!(bytemuck::bytes_of(material), bytemuck::bytes_of(expected), "{material:?} != {expected:?}");
+    }
+
+    #[test]
+    fn illumination_models_select_the_material_kind() {
+        let text: String = (0..=9)
+            .map(|illum| format!("newmtl illum{illum}\nKs 0.9 0.9 0.9\nillum {illum}\n"))
+            .collect();
+        let (materials, _) = parse_library("mtl_illum", &text, &[]).unwrap();
+        for (illum, kind) in [
+            (0, MaterialKind::Diffuse),
+            (1, MaterialKind::Diffuse),
+            (2, MaterialKind::Diffuse),
+            (3, MaterialKind::Metal),
+            (4, MaterialKind::Glass),
+            (5, MaterialKind::Metal),
+            (6, MaterialKind::Glass),
+            (7, MaterialKind::Glass),
+            (8, MaterialKind::Diffuse),
+            (9, MaterialKind::Glass),
+        ] {
+            assert_eq!(materials[&format!("illum{illum}")].kind(), kind, "illum {illum}");
+        }
+        // Mirrors reflect Ks rather than Kd.
+        assert_same(&materials["illum3"], &Material::metal([0.9; 3], 1.0).unwrap());
+    }
+
+    #[test]
+    fn dissolve_becomes_transmission() {
+        let text = "\
+newmtl dissolved
+d 0.25
 
-}
\ No newline at end of file
+newmtl transparent
+Tr 0.25
+
+newmtl principled_dissolved
+Pr 0.5
+d 0.25
+
+newmtl principled_transparent
+Pr 0.5
+Tr 0.25
+";
+        let (materials, _) = parse_library("mtl_dissolve", text, &[]).unwrap();
+        assert_eq!(materials["dissolved"].kind(), MaterialKind::Glass);
+        assert_eq!(materials["transparent"].kind(), MaterialKind::Glass);
+
+        let principled = |transmission| {
+            Material::principled(&Principled {
+                base_color: [0.8; 3],
+                roughness: 0.5,
+                transmission,
+                ior: 1.5,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        assert_same(&materials["principled_dissolved"], &principled(0.75));
+        assert_same(&materials["principled_transparent"], &principled(0.25));
+    }
+
+    #[test]
+    fn texture_maps_resolve_against_the_library() {
+        let text = "\
+newmtl textured
+Kd 1 1 1
+map_Kd textures/albedo.png
+map_Bump -bm 0.5 textures/normal.png
+
+newmtl missing
+map_Kd textures/missing.png
+";
+        let images = ["textures/albedo.png", "textures/normal.png"];
+        let (materials, textures) = parse_library("mtl_maps", text, &images).unwrap();
+        assert_eq!(textures, 2);
+        let textured = Material::diffuse([1.0; 3])
+            .with_texture(TextureSlot::Albedo, 0)
+            .with_texture(TextureSlot::Normal, 1);
+        assert_same(&materials["textured"], &textured);
+        // Maps that fail to load are left out.
+        assert_same(&materials["missing"], &Material::diffuse([0.8; 3]));
+    }
+
+    #[test]
+    fn library_errors_report_their_line() {
+        let dir = std::env::temp_dir().join(format!("mtl_error_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("library.mtl"), "newmtl red\nKd 1 0 0\n\nnewmtl green\nKd 0 one 0\n").unwrap();
+        let obj = dir.join("model.obj");
+        std::fs::write(&obj, "v 0 0 0\nmtllib library.mtl\n").unwrap();
+        let result = parse_obj(&obj.to_string_lossy(), &ImportOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(ObjError::Mtl { path, source }) => {
+                assert!(path.ends_with("library.mtl"), "{path}");
+                assert!(matches!(*source, ObjError::Parse { line: 5, .. }), "{source}");
+            }
+            other => panic!("expected a material library error, got {other:?}"),
+        }
+    }
+}
@@ -1,14 +1,21 @@
 pub mod bind_groups;
+pub mod bookmarks;
+pub mod bvh;
 pub mod camera;
+pub mod camera_path;
+pub mod display;
+pub mod environment_map;
+pub mod gltf_import;
+pub mod image_texture;
+pub mod lights;
+pub mod material;
 pub mod renderer;
 pub mod scene;
+pub mod scene_file;
 pub mod texture;
 pub mod pipelines;
 pub mod mesh;
+pub mod output;
+pub mod pick;
 
 pub use pipelines::*;
-pub use bind_groups::*;
-pub use camera::*;
-pub use scene::*;
-pub use texture::*;
-pub use mesh::*;
\ No newline at end of file
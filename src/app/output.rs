@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Copies a storage buffer of RGBA f32 pixels back to the CPU, blocking until the
+// GPU has finished every submission that writes it.
+pub fn read_pixel_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<[f32; 4]> {
+    read_buffer(device, queue, buffer)
+}
+
+// Copies any buffer with COPY_SRC usage back to the CPU, as with `read_pixel_buffer`.
+pub fn read_buffer<T: bytemuck::Pod>(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<T> {
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| {
+        if let Err(err) = result {
+            log::error!("Failed to map readback buffer: {err}");
+        }
+    });
+    if let Err(err) = device.poll(wgpu::PollType::Wait) {
+        log::error!("Failed to wait for readback: {err}");
+    }
+
+    let contents = bytemuck::pod_collect_to_vec(&slice.get_mapped_range());
+    staging.unmap();
+    contents
+}
+
+// 8-bit sRGB PNG of the clamped image.
+pub fn write_png(path: &Path, width: u32, height: u32, pixels: &[[f32; 4]]) -> Result<(), image::ImageError> {
+    let bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|p| [p[0], p[1], p[2]])
+        .map(|c| (linear_to_srgb(c) * 255.0 + 0.5) as u8)
+        .collect();
+    image::save_buffer(path, &bytes, width, height, image::ExtendedColorType::Rgb8)
+}
+
+// Linear 32-bit float OpenEXR, without any clamping or transfer function.
+pub fn write_exr(path: &Path, width: u32, height: u32, pixels: &[[f32; 4]]) -> Result<(), image::ImageError> {
+    let floats: Vec<f32> = pixels.iter().flat_map(|p| [p[0], p[1], p[2], 1.0]).collect();
+    image::save_buffer(path, bytemuck::cast_slice(&floats), width, height, image::ExtendedColorType::Rgba32F)
+}
+
+// `screenshot-<unix time in ms>` next to the running executable, or in the working
+// directory when its location is unknown. The caller adds the extension.
+pub fn screenshot_path() -> PathBuf {
+    timestamped_path("screenshot")
+}
+
+// Where camera paths recorded in the viewer go when none was given, named and
+// placed like screenshots.
+pub fn recorded_camera_path() -> PathBuf {
+    timestamped_path("camera-path").with_extension("toml")
+}
+
+// The running executable's directory, or the working directory when its location
+// is unknown.
+pub fn output_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_default()
+}
+
+fn timestamped_path(prefix: &str) -> PathBuf {
+    let dir = output_dir();
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    dir.join(format!("{prefix}-{millis}"))
+}
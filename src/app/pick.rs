@@ -0,0 +1,137 @@
+use wgpu::util::DeviceExt;
+
+use crate::app::output::read_buffer;
+use crate::bind_groups::BindGroups;
+use crate::pipelines::create_pick_pipeline;
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickResult {
+    position: [f32; 3],
+    distance: f32,
+    triangle: u32,
+    material: u32,
+    hit: u32,
+    _pad: u32,
+}
+
+// First surface under a pixel. `triangle` indexes the scene's triangles, which
+// are in BVH order, and `material` its materials.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PickHit {
+    pub position: [f32; 3],
+    // Along the ray from the camera position.
+    pub distance: f32,
+    pub triangle: u32,
+    pub material: u32,
+}
+
+// Traces single rays through the current camera and scene on the GPU and reads
+// back what they hit.
+pub struct Picker {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    request_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+}
+
+impl Picker {
+    pub fn new(device: &wgpu::Device, bind_groups: &BindGroups) -> Picker {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Pick Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let request_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Pick Request Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let result_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Pick Result Buffer"),
+            contents: bytemuck::cast_slice(&[PickResult::default()]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Pick Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: request_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: result_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Picker {
+            pipeline: create_pick_pipeline(device, bind_groups, &layout),
+            bind_group,
+            request_buffer,
+            result_buffer,
+        }
+    }
+
+    // Blocks until the GPU has traced the ray through pixel (`x`, `y`), counted from
+    // the top left.
+    pub fn pick(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_groups: &BindGroups,
+        x: u32,
+        y: u32,
+    ) -> Option<PickHit> {
+        queue.write_buffer(&self.request_buffer, 0, bytemuck::cast_slice(&[x, y, 0, 0]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pick Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Pick Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_groups.compute_bind_group, &[]);
+            compute_pass.set_bind_group(1, &bind_groups.camera_bind_group, &[]);
+            compute_pass.set_bind_group(2, &bind_groups.scene_bind_group, &[]);
+            compute_pass.set_bind_group(3, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let result: PickResult = read_buffer(device, queue, &self.result_buffer)[0];
+        (result.hit != 0).then_some(PickHit {
+            position: result.position,
+            distance: result.distance,
+            triangle: result.triangle,
+            material: result.material,
+        })
+    }
+}
@@ -1,95 +1,120 @@
 use crate::bind_groups::*;
 
 
-pub struct Pipelines {
-    pub render_pipeline: wgpu::RenderPipeline,
-    pub compute_pipeline: wgpu::ComputePipeline,
-}
+// Draws the accumulated image to a target of `format` with a fullscreen triangle.
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    bind_groups: &BindGroups,
+) -> wgpu::RenderPipeline {
+    let raster_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Raster Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/raster.wgsl").into()),
+    });
 
-impl Pipelines {
-    pub fn new(
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        bind_groups: &BindGroups,
-    ) -> Pipelines {
-        let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Compute Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/compute.wgsl").into()),
-        });
-        let raster_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Raster Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/raster.wgsl").into()),
+    let render_pipeline_layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[&bind_groups.fragment_bind_group_layout],
+            push_constant_ranges: &[],
         });
 
-        let compute_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Compute Pipeline Layout"),
-                bind_group_layouts: &[
-                    &bind_groups.compute_bind_group_layout,
-                    &bind_groups.camera_bind_group_layout,
-                    &bind_groups.scene_bind_group_layout,
-                    &bind_groups.texture_buffer_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute Pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: Some("raytrace"),
-            cache: None,
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &raster_shader,
+            entry_point: Some("vs_main"),
             compilation_options: wgpu::PipelineCompilationOptions::default(),
-        });
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &raster_shader,
+            entry_point: Some("fs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&bind_groups.fragment_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+pub fn create_compute_pipeline(device: &wgpu::Device, bind_groups: &BindGroups) -> wgpu::ComputePipeline {
+    let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Compute Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/compute.wgsl").into()),
+    });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &raster_shader,
-                entry_point: Some("vs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &raster_shader,
-                entry_point: Some("fs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
+    let compute_pipeline_layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Compute Pipeline Layout"),
+            bind_group_layouts: &[
+                &bind_groups.compute_bind_group_layout,
+                &bind_groups.camera_bind_group_layout,
+                &bind_groups.scene_bind_group_layout,
+                &bind_groups.accumulation_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
         });
 
-        Pipelines {
-            render_pipeline,
-            compute_pipeline,
-        }
-    }
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Compute Pipeline"),
+        layout: Some(&compute_pipeline_layout),
+        module: &compute_shader,
+        entry_point: Some("raytrace"),
+        cache: None,
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+    })
+}
+
+// Runs the shader's `pick` entry point with the scene bind groups and `pick_layout`
+// as group 3.
+pub fn create_pick_pipeline(
+    device: &wgpu::Device,
+    bind_groups: &BindGroups,
+    pick_layout: &wgpu::BindGroupLayout,
+) -> wgpu::ComputePipeline {
+    let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Pick Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/compute.wgsl").into()),
+    });
+
+    let pick_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Pick Pipeline Layout"),
+        bind_group_layouts: &[
+            &bind_groups.compute_bind_group_layout,
+            &bind_groups.camera_bind_group_layout,
+            &bind_groups.scene_bind_group_layout,
+            pick_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Pick Pipeline"),
+        layout: Some(&pick_pipeline_layout),
+        module: &compute_shader,
+        entry_point: Some("pick"),
+        cache: None,
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+    })
 }
@@ -0,0 +1,421 @@
+use std::error::Error;
+use std::fmt;
+
+use wgpu::util::DeviceExt;
+
+use crate::app::output::read_pixel_buffer;
+use crate::bind_groups::{required_limits, BindGroups};
+use crate::camera::{Camera, CameraPose};
+use crate::display::{Display, DisplaySettings};
+use crate::lights::LightSource;
+use crate::mesh::ImportOptions;
+use crate::pick::{PickHit, Picker};
+use crate::pipelines::create_compute_pipeline;
+use crate::scene::Scene;
+use crate::scene_file::SceneDescription;
+use crate::texture::Textures;
+
+#[derive(Debug)]
+pub enum RendererError {
+    NoAdapter(wgpu::RequestAdapterError),
+    Device(wgpu::RequestDeviceError),
+    Poll(wgpu::PollError),
+    Scene(Box<dyn Error>),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::NoAdapter(err) => write!(f, "no graphics adapter available: {err}"),
+            RendererError::Device(err) => write!(f, "failed to create device: {err}"),
+            RendererError::Poll(err) => write!(f, "failed to wait for the GPU: {err}"),
+            RendererError::Scene(err) => write!(f, "failed to load scene: {err}"),
+        }
+    }
+}
+
+impl Error for RendererError {}
+
+impl From<wgpu::RequestAdapterError> for RendererError {
+    fn from(err: wgpu::RequestAdapterError) -> Self {
+        RendererError::NoAdapter(err)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for RendererError {
+    fn from(err: wgpu::RequestDeviceError) -> Self {
+        RendererError::Device(err)
+    }
+}
+
+impl From<wgpu::PollError> for RendererError {
+    fn from(err: wgpu::PollError) -> Self {
+        RendererError::Poll(err)
+    }
+}
+
+impl From<Box<dyn Error>> for RendererError {
+    fn from(err: Box<dyn Error>) -> Self {
+        RendererError::Scene(err)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrameUniform {
+    global_frame_info: [u32; 4],
+    // x: seed mixed into the shader's random numbers.
+    render_info: [u32; 4],
+}
+
+#[derive(Debug, Clone)]
+pub struct RendererOptions {
+    pub width: u32,
+    pub height: u32,
+    pub backends: wgpu::Backends,
+    pub force_fallback_adapter: bool,
+    pub samples_per_frame: u32,
+    pub max_bounces: u32,
+    pub seed: u32,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        RendererOptions {
+            width: 800,
+            height: 600,
+            backends: wgpu::Backends::all(),
+            force_fallback_adapter: false,
+            samples_per_frame: 4,
+            max_bounces: 10,
+            seed: 0,
+        }
+    }
+}
+
+// Path tracer that owns its device. Frames add to a running mean in the
+// accumulation buffer until the scene, camera or size changes. Used offscreen on
+// its own, and by the viewer, which draws the image to its window.
+pub struct Renderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    sampler: wgpu::Sampler,
+    size: winit::dpi::PhysicalSize<u32>,
+    seed: u32,
+    camera: Camera,
+    display: Display,
+    target_samples: Option<u32>,
+    scene: Scene,
+    textures: Textures,
+    frame_uniform: FrameUniform,
+    frame_buffer: Option<wgpu::Buffer>,
+    bind_groups: BindGroups,
+    compute_pipeline: wgpu::ComputePipeline,
+    picker: Picker,
+}
+
+impl Renderer {
+    pub async fn new(options: &RendererOptions) -> Result<Renderer, RendererError> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: options.backends,
+            ..Default::default()
+        });
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: options.force_fallback_adapter,
+            })
+            .await?;
+        log::info!("Rendering offscreen on {}", adapter.get_info().name);
+        Renderer::from_adapter(&adapter, options).await
+    }
+
+    // Renders on a device of `adapter`, such as one that can present to a window.
+    // `backends` and `force_fallback_adapter` only matter to `new`.
+    pub async fn from_adapter(adapter: &wgpu::Adapter, options: &RendererOptions) -> Result<Renderer, RendererError> {
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                required_features: wgpu::Features::empty(),
+                required_limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    required_limits(adapter)
+                },
+                label: None,
+                memory_hints: Default::default(),
+                trace: wgpu::Trace::Off,
+            })
+            .await?;
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let size = winit::dpi::PhysicalSize::new(options.width.max(1), options.height.max(1));
+        let camera = Camera::new(&size, &device, &queue, std::f32::consts::FRAC_PI_2);
+        let display = Display::new(&device, DisplaySettings::default());
+        let scene = Scene::new(&device, options.seed as u64);
+        let textures = Textures::new(&device, &size);
+
+        let frame_uniform = FrameUniform {
+            global_frame_info: [0, 0, options.max_bounces, options.samples_per_frame.max(1)],
+            render_info: [options.seed, 0, 0, 0],
+        };
+        let frame_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Frame Buffer"),
+            contents: bytemuck::cast_slice(&[frame_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        }));
+
+        let bind_groups = BindGroups::new(
+            &device,
+            &sampler,
+            &scene,
+            &camera.buffer,
+            &frame_buffer,
+            &textures.accumulation_buffer,
+            &textures.surface_texture_view,
+            &display.buffer,
+        );
+        let compute_pipeline = create_compute_pipeline(&device, &bind_groups);
+        let picker = Picker::new(&device, &bind_groups);
+
+        Ok(Renderer {
+            device,
+            queue,
+            sampler,
+            size,
+            seed: options.seed,
+            camera,
+            display,
+            target_samples: None,
+            scene,
+            textures,
+            frame_uniform,
+            frame_buffer,
+            bind_groups,
+            compute_pipeline,
+            picker,
+        })
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub(crate) fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub(crate) fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub(crate) fn bind_groups(&self) -> &BindGroups {
+        &self.bind_groups
+    }
+
+    pub fn width(&self) -> u32 {
+        self.size.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.size.height
+    }
+
+    // Samples per pixel averaged into the current image.
+    pub fn accumulated_samples(&self) -> u32 {
+        self.frame_uniform.global_frame_info[1]
+    }
+
+    pub fn samples_per_frame(&self) -> u32 {
+        self.frame_uniform.global_frame_info[3]
+    }
+
+    pub fn target_samples(&self) -> Option<u32> {
+        self.target_samples
+    }
+
+    // Once the image has this many samples per pixel, `render_frames` stops tracing.
+    pub fn set_target_samples(&mut self, target: Option<u32>) {
+        self.target_samples = target;
+    }
+
+    pub fn is_converged(&self) -> bool {
+        self.target_samples
+            .is_some_and(|target| self.accumulated_samples() >= target)
+    }
+
+    // Replaces the scene and applies its camera and render settings, if it has any.
+    pub fn set_scene(&mut self, description: &SceneDescription) -> Result<(), RendererError> {
+        let mut scene = Scene::new(&self.device, self.seed as u64);
+        scene.load_description(description, &self.device, &self.queue)?;
+        if let Some(samples) = description.render.samples {
+            self.set_samples_per_frame(samples);
+        }
+        if let Some(max_bounces) = description.render.max_bounces {
+            self.set_max_bounces(max_bounces);
+        }
+        if let Some(target) = description.render.target_samples {
+            self.set_target_samples(Some(target));
+        }
+        let mut display = self.display.settings;
+        display.apply_render_settings(&description.render);
+        self.set_display(display);
+        self.replace_scene(scene);
+        Ok(())
+    }
+
+    // Replaces the scene with a single .obj, .gltf or .glb model.
+    pub fn load_model(&mut self, path: &str, options: &ImportOptions) -> Result<(), RendererError> {
+        let mut scene = Scene::new(&self.device, self.seed as u64);
+        scene.load_model(path, options, &self.device)?;
+        self.replace_scene(scene);
+        Ok(())
+    }
+
+    pub fn load_test_scene(&mut self) -> Result<(), RendererError> {
+        let mut scene = Scene::new(&self.device, self.seed as u64);
+        scene.setup_test_scene(&self.device)?;
+        self.replace_scene(scene);
+        Ok(())
+    }
+
+    fn replace_scene(&mut self, scene: Scene) {
+        self.scene = scene;
+        self.bind_groups.rebuild_scene_bind_group(&self.device, &self.scene);
+        if let Some(pose) = self.scene.camera {
+            self.camera.set_pose(&pose, &self.queue);
+        }
+        self.reset_accumulation();
+    }
+
+    // Replaces the analytic lights, keeping the scene's emissive geometry.
+    pub fn set_lights(&mut self, lights: Vec<LightSource>) {
+        self.scene.light_sources = lights;
+        self.scene.update_light_buffer(&self.device);
+        self.bind_groups.rebuild_scene_bind_group(&self.device, &self.scene);
+        self.reset_accumulation();
+    }
+
+    pub fn set_camera(&mut self, pose: &CameraPose) {
+        self.camera.set_pose(pose, &self.queue);
+        self.reset_accumulation();
+    }
+
+    pub fn camera_pose(&self) -> CameraPose {
+        self.camera.pose()
+    }
+
+    pub fn set_samples_per_frame(&mut self, samples: u32) {
+        self.frame_uniform.global_frame_info[3] = samples.max(1);
+        self.reset_accumulation();
+    }
+
+    pub fn set_max_bounces(&mut self, max_bounces: u32) {
+        self.frame_uniform.global_frame_info[2] = max_bounces;
+        self.reset_accumulation();
+    }
+
+    // `read_pixels` stays linear; these are for `DisplaySettings::apply` before an
+    // image is written at 8 bits. Changing them keeps the accumulation.
+    pub fn set_display(&mut self, settings: DisplaySettings) {
+        self.display.set(settings, &self.queue);
+    }
+
+    pub fn display_settings(&self) -> DisplaySettings {
+        self.display.settings
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.size = winit::dpi::PhysicalSize::new(width.max(1), height.max(1));
+        self.camera.camera.resize(self.size);
+        self.camera.build_uniform();
+        self.camera.update_buffer(&self.queue);
+
+        self.textures = Textures::new(&self.device, &self.size);
+        self.bind_groups.rebuild_compute_bind_group(
+            &self.device,
+            &self.frame_buffer,
+            &self.textures.surface_texture_view,
+        );
+        self.bind_groups.rebuild_fragment_bind_group(
+            &self.device,
+            &self.sampler,
+            &self.textures.surface_texture_view,
+            &self.display.buffer,
+        );
+        self.bind_groups
+            .rebuild_accumulation_bind_group(&self.device, &self.textures.accumulation_buffer);
+        self.reset_accumulation();
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.frame_uniform.global_frame_info[1] = 0;
+    }
+
+    // Traces `frames` more frames of `samples_per_frame` samples each, or fewer
+    // once the target sample count is reached, and blocks until the GPU has
+    // finished them.
+    pub fn render_frames(&mut self, frames: u32) -> Result<(), RendererError> {
+        for _ in 0..frames {
+            if self.is_converged() {
+                break;
+            }
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Offscreen Encoder"),
+                });
+            self.encode_trace(&mut encoder);
+            self.queue.submit(std::iter::once(encoder.finish()));
+            self.device.poll(wgpu::PollType::Wait)?;
+            self.finish_frame();
+        }
+        Ok(())
+    }
+
+    // Records one frame of tracing into `encoder`. Call `finish_frame` once it has
+    // been submitted.
+    pub(crate) fn encode_trace(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(buffer) = &self.frame_buffer {
+            self.queue
+                .write_buffer(buffer, 0, bytemuck::cast_slice(&[self.frame_uniform]));
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_groups.compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_groups.camera_bind_group, &[]);
+        compute_pass.set_bind_group(2, &self.bind_groups.scene_bind_group, &[]);
+        compute_pass.set_bind_group(3, &self.bind_groups.accumulation_bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.size.width.div_ceil(8), self.size.height.div_ceil(8), 1);
+    }
+
+    // Counts the frame last recorded by `encode_trace` into the image.
+    pub(crate) fn finish_frame(&mut self) {
+        self.frame_uniform.global_frame_info[0] += 1;
+        self.frame_uniform.global_frame_info[1] += self.frame_uniform.global_frame_info[3];
+    }
+
+    // First surface under pixel (`x`, `y`), counted from the top left.
+    pub fn pick(&self, x: u32, y: u32) -> Option<PickHit> {
+        self.picker.pick(&self.device, &self.queue, &self.bind_groups, x, y)
+    }
+
+    // Linear RGBA pixels of the accumulated image, row by row from the top.
+    pub fn read_pixels(&self) -> Vec<[f32; 4]> {
+        read_pixel_buffer(&self.device, &self.queue, &self.textures.accumulation_buffer)
+    }
+}
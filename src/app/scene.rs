@@ -1,41 +1,230 @@
 //use rand::prelude::*;
 use std::vec;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use wgpu::util::DeviceExt;
+use std::collections::HashMap;
+use cgmath::{Matrix4, Vector4};
+use crate::bookmarks::Bookmarks;
+use crate::bvh::*;
+use crate::camera::CameraPose;
+use crate::environment_map::EnvironmentMap;
+use crate::gltf_import::*;
+use crate::image_texture::*;
+use crate::lights::*;
+use crate::material::*;
 use crate::mesh::*;
+use crate::scene_file::*;
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Environment {
+    top_color_and_intensity: [f32; 4],
+    // w: rotation of the environment map about the up axis, in radians.
+    bottom_color_and_rotation: [f32; 4],
+    // x, y: environment map size, z: 1 when a map replaces the gradient.
+    map_info: [u32; 4],
+}
+
+impl Environment {
+    pub fn new(top_color: [f32; 3], bottom_color: [f32; 3], intensity: f32) -> Self {
+        Environment {
+            top_color_and_intensity: [top_color[0], top_color[1], top_color[2], intensity],
+            bottom_color_and_rotation: [bottom_color[0], bottom_color[1], bottom_color[2], 0.0],
+            map_info: [0; 4],
+        }
+    }
+
+    pub fn with_map(mut self, map: &EnvironmentMap, rotation_degrees: f32) -> Self {
+        self.bottom_color_and_rotation[3] = rotation_degrees.to_radians();
+        self.map_info = [map.width, map.height, 1, 0];
+        self
+    }
+}
+
+// Shading attributes of a vertex, parallel to the scene's vertices. A zero normal
+// makes the shader fall back to the face normal.
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Material {
-    albedo_and_mat: [f32; 4],
-    emission_and_roughness: [f32; 4],
-    ior: [f32; 4],
-}
-
-impl Material {
-    pub fn new(albedo: [f32; 3], emission: [f32; 3], material: f32, roughness: f32, ior: f32) -> Self {
-        Material {
-            albedo_and_mat: [albedo[0], albedo[1], albedo[2], material],
-            emission_and_roughness: [emission[0], emission[1], emission[2], roughness],
-            ior: [ior, 0.0, 0.0, 0.0],
+pub struct VertexAttributes {
+    normal: [f32; 3],
+    _pad0: f32,
+    uv: [f32; 2],
+    _pad1: [f32; 2],
+}
+
+impl VertexAttributes {
+    pub fn new(normal: [f32; 3], uv: [f32; 2]) -> Self {
+        VertexAttributes {
+            normal,
+            uv,
+            ..VertexAttributes::default()
         }
     }
 }
 
+// Must match `luminance` in compute.wgsl, which recomputes the power of emissive
+// triangles hit by BSDF sampled rays.
+pub fn luminance(color: [f32; 3]) -> f32 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new([0.529, 0.808, 0.922], [0.0, 0.4, 0.8], 1.0)
+    }
+}
+
+pub fn load_mesh_file(path: &str, options: &ImportOptions) -> Result<(Mesh, Option<CameraPose>), Box<dyn std::error::Error>> {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => {
+            let gltf = load_gltf(path, options)?;
+            Ok((gltf.mesh, gltf.camera))
+        }
+        _ => Ok((parse_obj(path, options)?, None)),
+    }
+}
+
+// An empty table is uploaded as a single zero-power entry, which the shader
+// treats as "no lights".
+fn create_light_buffer(device: &wgpu::Device, lights: &[Light]) -> wgpu::Buffer {
+    let placeholder = [Light::default()];
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Light Buffer"),
+        contents: bytemuck::cast_slice(if lights.is_empty() { &placeholder } else { lights }),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_environment_buffer(device: &wgpu::Device, environment: &Environment) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment Buffer"),
+        contents: bytemuck::cast_slice(&[*environment]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// Without a map a single placeholder texel keeps the binding valid.
+fn create_environment_map_buffer(device: &wgpu::Device, map: Option<&EnvironmentMap>) -> wgpu::Buffer {
+    let table = map.map_or(vec![[0.0; 4]], EnvironmentMap::sampling_table);
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment Map Buffer"),
+        contents: bytemuck::cast_slice(&table),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// Tables larger than the device can bind are dropped, leaving every material
+// untextured; the shader treats texture headers it cannot read as missing.
+fn create_texture_buffer(device: &wgpu::Device, textures: &[ImageTexture]) -> wgpu::Buffer {
+    let mut table = texture_table(textures);
+    let limit = device.limits().max_storage_buffer_binding_size as usize;
+    if table.len() * 4 > limit {
+        log::error!("Textures need {} bytes, more than the {limit} the device can bind; rendering without them", table.len() * 4);
+        table.clear();
+    }
+    if table.is_empty() {
+        table = vec![0; 4];
+    }
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Texture Buffer"),
+        contents: bytemuck::cast_slice(&table),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_vertex_attribute_buffer(device: &wgpu::Device, attributes: &[VertexAttributes]) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Vertex Attribute Buffer"),
+        contents: bytemuck::cast_slice(attributes),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn mesh_vertex_attributes(mesh: &Mesh) -> impl Iterator<Item = VertexAttributes> + '_ {
+    mesh.normals
+        .iter()
+        .zip(&mesh.uvs)
+        .map(|(n, &uv)| VertexAttributes::new([n[0], n[1], n[2]], uv))
+}
+
+// Quad lights are appended to the geometry as two emissive triangles each; the
+// other kinds are returned as light sources.
+fn add_lights(
+    lights: &[LightDescription],
+    materials: &mut Vec<Material>,
+    vertices: &mut Vec<[f32; 4]>,
+    vertex_attributes: &mut Vec<VertexAttributes>,
+    tris: &mut Vec<[u32; 4]>,
+) -> Result<Vec<LightSource>, MaterialError> {
+    let mut light_sources = Vec::new();
+    for light in lights {
+        match light {
+            LightDescription::Quad { corner, edge_u, edge_v, emission } => {
+                materials.push(Material::emissive(*emission)?);
+                let material = materials.len() as u32 - 1;
+                let base = vertices.len() as u32;
+                for (u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                    vertices.push([
+                        corner[0] + edge_u[0] * u + edge_v[0] * v,
+                        corner[1] + edge_u[1] * u + edge_v[1] * v,
+                        corner[2] + edge_u[2] * u + edge_v[2] * v,
+                        1.0,
+                    ]);
+                    vertex_attributes.push(VertexAttributes::default());
+                }
+                tris.push([base, base + 1, base + 2, material]);
+                tris.push([base, base + 2, base + 3, material]);
+            }
+            _ => light_sources.extend(light.source()),
+        }
+    }
+    Ok(light_sources)
+}
+
+fn euler_rotation(degrees: [f32; 3]) -> Matrix4<f32> {
+    Matrix4::from_angle_z(cgmath::Deg(degrees[2]))
+        * Matrix4::from_angle_y(cgmath::Deg(degrees[1]))
+        * Matrix4::from_angle_x(cgmath::Deg(degrees[0]))
+}
+
 pub struct Scene {
     pub materials: Vec<Material>,
     pub vertices: Vec<[f32; 4]>,
+    pub vertex_attributes: Vec<VertexAttributes>,
     pub tris: Vec<[u32; 4]>,
+    pub textures: Vec<ImageTexture>,
+    pub bvh_nodes: Vec<BvhNode>,
+    pub camera: Option<CameraPose>,
+    // Viewpoints saved in the scene file.
+    pub bookmarks: Bookmarks,
+    pub environment: Environment,
+    pub light_sources: Vec<LightSource>,
+    pub light_table: Vec<Light>,
     pub material_buffer: wgpu::Buffer,
     pub vertex_buffer: wgpu::Buffer,
+    pub vertex_attribute_buffer: wgpu::Buffer,
     pub tri_buffer: wgpu::Buffer,
+    pub bvh_buffer: wgpu::Buffer,
+    pub environment_buffer: wgpu::Buffer,
+    pub light_buffer: wgpu::Buffer,
+    pub environment_map_buffer: wgpu::Buffer,
+    pub texture_buffer: wgpu::Buffer,
+    rng: StdRng,
 }
 
 impl Scene {
-    pub fn new(device: &wgpu::Device) -> Scene {
+    pub fn new(device: &wgpu::Device, seed: u64) -> Scene {
         let materials = vec![Material::default()];
         let vertices = vec![[0.0; 4]];
-        let tris = vec![[0; 4]];
+        let vertex_attributes = vec![VertexAttributes::default()];
+        let mut tris = vec![[0; 4]];
+        let bvh_nodes = build_bvh(&vertices, &mut tris);
+        let light_table = Vec::new();
 
         let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Material Buffer"),
@@ -55,42 +244,237 @@ impl Scene {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
+        let bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BVH Buffer"),
+            contents: bytemuck::cast_slice(&bvh_nodes),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let environment = Environment::default();
+        let environment_buffer = create_environment_buffer(device, &environment);
+
+        let vertex_attribute_buffer = create_vertex_attribute_buffer(device, &vertex_attributes);
+        let light_buffer = create_light_buffer(device, &light_table);
+        let environment_map_buffer = create_environment_map_buffer(device, None);
+        let texture_buffer = create_texture_buffer(device, &[]);
+
         Scene {
             materials,
             vertices,
+            vertex_attributes,
             tris,
+            textures: Vec::new(),
+            bvh_nodes,
+            camera: None,
+            bookmarks: Bookmarks::default(),
+            environment,
+            light_sources: Vec::new(),
+            light_table,
             material_buffer,
             vertex_buffer,
-            tri_buffer
+            vertex_attribute_buffer,
+            tri_buffer,
+            bvh_buffer,
+            environment_buffer,
+            light_buffer,
+            environment_map_buffer,
+            texture_buffer,
+            rng: StdRng::seed_from_u64(seed),
         }
     }
 
-    pub fn setup_test_scene(&mut self, device: &wgpu::Device) {
+    pub fn setup_test_scene(&mut self, device: &wgpu::Device) -> Result<(), Box<dyn std::error::Error>> {
+        self.load_model("models/apple.obj", &ImportOptions::uniform(10.0), device)
+    }
+
+    // Replaces the scene with a single .obj, .gltf or .glb model under the
+    // default sky.
+    pub fn load_model(&mut self, path: &str, options: &ImportOptions, device: &wgpu::Device) -> Result<(), Box<dyn std::error::Error>> {
+        let (mesh, camera) = load_mesh_file(path, options)?;
+        self.vertex_attributes = mesh_vertex_attributes(&mesh).collect();
+
         self.materials.clear();
-        self.vertices.clear();
-        self.tris.clear();
+        if mesh.materials.is_empty() {
+            self.materials.push(Material::glass(self.rng.random(), 0.0, 1.5)?);
+        } else {
+            self.materials = mesh.materials;
+        }
+
+        self.vertices = mesh.vertices;
+        self.tris = mesh.triangles;
+        self.textures = mesh.textures;
+        self.camera = camera;
+        self.bookmarks = Bookmarks::default();
+        self.light_sources.clear();
+        self.environment = Environment::default();
+        self.environment_buffer = create_environment_buffer(device, &self.environment);
+        self.environment_map_buffer = create_environment_map_buffer(device, None);
+
+        self.update_material_buffer(device);
+        self.update_triangle_buffers(device);
+        Ok(())
+    }
 
-        self.materials
-            .push(Material::new([rand::rng().random::<f32>(), rand::rng().random::<f32>(), rand::rng().random::<f32>()], [0.0; 3], 2.0, 0.5, 1.5));
+    // Replaces the scene with the meshes, materials, lights and environment of a
+    // scene file. The first camera found (the file's own, then any imported one)
+    // is stored in `camera`.
+    pub fn load_description(
+        &mut self,
+        description: &SceneDescription,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut materials: Vec<Material> = Vec::new();
+        let mut vertices: Vec<[f32; 4]> = Vec::new();
+        let mut vertex_attributes: Vec<VertexAttributes> = Vec::new();
+        let mut tris: Vec<[u32; 4]> = Vec::new();
+        let mut textures: Vec<ImageTexture> = Vec::new();
+        let mut camera = description.camera.map(|c| c.pose());
 
-        (self.vertices, self.tris) = parse_obj("models/apple.obj");
+        let mut named_materials: HashMap<&str, u32> = HashMap::new();
+        for (name, material) in &description.materials {
+            named_materials.insert(name, materials.len() as u32);
+            materials.push(material.to_material()?);
+        }
+        let mut default_material: Option<u32> = None;
+
+        for mesh_description in &description.meshes {
+            let path = description.mesh_path(mesh_description);
+            let (mesh, mesh_camera) = load_mesh_file(&path.to_string_lossy(), &mesh_description.import_options())?;
+
+            let rotation = euler_rotation(mesh_description.rotation);
+            let transform = Matrix4::from_translation(mesh_description.translation.into()) * rotation;
+
+            let vertex_offset = vertices.len() as u32;
+            vertices.extend(mesh.vertices.iter().map(|&v| -> [f32; 4] { (transform * Vector4::from(v)).into() }));
+            vertex_attributes.extend(mesh_vertex_attributes(&mesh).map(|mut attributes| {
+                let normal = rotation * Vector4::new(attributes.normal[0], attributes.normal[1], attributes.normal[2], 0.0);
+                attributes.normal = normal.truncate().into();
+                attributes
+            }));
+
+            let material_offset = materials.len() as u32;
+            let mut material_override = mesh_description.material.as_deref().map(|name| named_materials[name]);
+            if material_override.is_none() {
+                if mesh.materials.is_empty() {
+                    material_override = Some(*default_material.get_or_insert_with(|| {
+                        materials.push(Material::diffuse([0.8; 3]));
+                        materials.len() as u32 - 1
+                    }));
+                } else {
+                    let texture_offset = textures.len() as u32;
+                    materials.extend(mesh.materials.iter().map(|&material| {
+                        let mut material = material;
+                        material.offset_textures(texture_offset);
+                        material
+                    }));
+                    textures.extend(mesh.textures);
+                }
+            }
+
+            for tri in &mesh.triangles {
+                tris.push([
+                    tri[0] + vertex_offset,
+                    tri[1] + vertex_offset,
+                    tri[2] + vertex_offset,
+                    material_override.unwrap_or(material_offset + tri[3]),
+                ]);
+            }
+
+            if camera.is_none() {
+                camera = mesh_camera.map(|pose| {
+                    let position = transform * Vector4::new(pose.position[0], pose.position[1], pose.position[2], 1.0);
+                    let forward = rotation * pose.forward().extend(0.0);
+                    CameraPose::looking_along(position.truncate().into(), forward.truncate().into(), pose.fov)
+                });
+            }
+        }
+
+        let light_sources =
+            add_lights(&description.lights, &mut materials, &mut vertices, &mut vertex_attributes, &mut tris)?;
+
+        // Keep the buffers non-empty so the bind groups stay valid.
+        if materials.is_empty() {
+            materials.push(Material::default());
+        }
+        if tris.is_empty() {
+            vertices.push([0.0; 4]);
+            vertex_attributes.push(VertexAttributes::default());
+            tris.push([0; 4]);
+        }
+
+        self.materials = materials;
+        self.vertices = vertices;
+        self.vertex_attributes = vertex_attributes;
+        self.tris = tris;
+        self.textures = textures;
+        self.camera = camera;
+        self.bookmarks = Bookmarks {
+            bookmarks: description.bookmarks.clone(),
+        };
+        self.light_sources = light_sources;
+
+        let environment = &description.environment;
+        self.environment = Environment::new(environment.top_color, environment.bottom_color, environment.intensity);
+        let map = match &environment.map {
+            Some(path) => Some(EnvironmentMap::load(&description.base_dir.join(path))?),
+            None => None,
+        };
+        if let Some(map) = &map {
+            self.environment = self.environment.with_map(map, environment.rotation);
+        }
+        self.environment_map_buffer = create_environment_map_buffer(device, map.as_ref());
+        queue.write_buffer(&self.environment_buffer, 0, bytemuck::cast_slice(&[self.environment]));
 
         self.update_material_buffer(device);
         self.update_triangle_buffers(device);
+        Ok(())
     }
 
     pub fn update_triangle_buffers(&mut self, device: &wgpu::Device) {
+        let start = std::time::Instant::now();
+        self.bvh_nodes = build_bvh(&self.vertices, &mut self.tris);
+        log::info!(
+            "Built BVH with {} nodes over {} triangles in {:?}",
+            self.bvh_nodes.len(),
+            self.tris.len(),
+            start.elapsed()
+        );
+
         self.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(&self.vertices),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
+        self.vertex_attribute_buffer = create_vertex_attribute_buffer(device, &self.vertex_attributes);
+
         self.tri_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Triangle Buffer"),
             contents: bytemuck::cast_slice(&self.tris),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
+
+        self.bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BVH Buffer"),
+            contents: bytemuck::cast_slice(&self.bvh_nodes),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        self.update_light_buffer(device);
+    }
+
+    // Rebuilds the light table from the emissive triangles and `light_sources`.
+    // Triangle indices refer to the BVH ordered triangle buffer, so this has to
+    // run after the BVH is built.
+    pub fn update_light_buffer(&mut self, device: &wgpu::Device) {
+        self.light_table = build_light_table(&self.vertices, &self.tris, &self.materials, &self.light_sources);
+        log::info!(
+            "Built light table with {} lights ({} analytic)",
+            self.light_table.len(),
+            self.light_sources.len()
+        );
+        self.light_buffer = create_light_buffer(device, &self.light_table);
     }
 
     pub fn update_material_buffer(&mut self, device: &wgpu::Device) {
@@ -99,5 +483,58 @@ impl Scene {
             contents: bytemuck::cast_slice(&self.materials),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
+        self.texture_buffer = create_texture_buffer(device, &self.textures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quad_lights_become_emissive_geometry() {
+        let description: SceneDescription = toml::from_str(
+            r#"
+            [[lights]]
+            type = "quad"
+            corner = [-1.0, -4.0, -1.0]
+            edge_u = [2.0, 0.0, 0.0]
+            edge_v = [0.0, 0.0, 2.0]
+            emission = [5.0, 4.0, 3.0]
+
+            [[lights]]
+            type = "point"
+            position = [0.0, -2.0, 0.0]
+            intensity = [1.0, 1.0, 1.0]
+            "#,
+        )
+        .unwrap();
+
+        let mut materials = vec![Material::default()];
+        let mut vertices = vec![[0.0; 4]];
+        let mut vertex_attributes = vec![VertexAttributes::default()];
+        let mut tris = Vec::new();
+        let sources =
+            add_lights(&description.lights, &mut materials, &mut vertices, &mut vertex_attributes, &mut tris).unwrap();
+
+        assert_eq!(sources.len(), 1);
+        assert!(matches!(sources[0], LightSource::Point { .. }));
+
+        assert_eq!(materials.len(), 2);
+        let emissive = Material::emissive([5.0, 4.0, 3.0]).unwrap();
+        assert_eq!(bytemuck::bytes_of(&materials[1]), bytemuck::bytes_of(&emissive));
+
+        // Appended after the existing vertex, covering the whole parallelogram.
+        assert_eq!(
+            vertices[1..],
+            [
+                [-1.0, -4.0, -1.0, 1.0],
+                [1.0, -4.0, -1.0, 1.0],
+                [1.0, -4.0, 1.0, 1.0],
+                [-1.0, -4.0, 1.0, 1.0],
+            ]
+        );
+        assert_eq!(vertex_attributes.len(), vertices.len());
+        assert_eq!(tris, [[1, 2, 3, 1], [1, 3, 4, 1]]);
     }
 }
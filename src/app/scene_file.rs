@@ -0,0 +1,578 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::bookmarks::Bookmark;
+use crate::camera::{CameraPose, Lens, Projection};
+use crate::display::Tonemap;
+use crate::lights::LightSource;
+use crate::mesh::{ImportOptions, UpAxis};
+use crate::material::{Material, MaterialError, Principled};
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    UnknownMaterial { mesh: String, material: String },
+    InvalidMaterial { name: String, source: MaterialError },
+    // A quad light whose emission no material can have; `index` counts from 1.
+    InvalidLight { index: usize, source: MaterialError },
+    // A camera f-number that is not positive.
+    InvalidFStop(f32),
+    NegativeAperture(f32),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(err) => write!(f, "failed to read scene file: {err}"),
+            SceneFileError::Parse(err) => write!(f, "invalid scene file: {err}"),
+            SceneFileError::UnknownMaterial { mesh, material } => {
+                write!(f, "mesh {mesh} uses undefined material `{material}`")
+            }
+            SceneFileError::InvalidMaterial { name, source } => write!(f, "material `{name}`: {source}"),
+            SceneFileError::InvalidLight { index, source } => write!(f, "light {index}: {source}"),
+            SceneFileError::InvalidFStop(f_stop) => write!(f, "camera f_stop must be greater than 0, got {f_stop}"),
+            SceneFileError::NegativeAperture(aperture) => {
+                write!(f, "camera aperture must not be negative, got {aperture}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(err: std::io::Error) -> Self {
+        SceneFileError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for SceneFileError {
+    fn from(err: toml::de::Error) -> Self {
+        SceneFileError::Parse(err)
+    }
+}
+
+const DEFAULT_ALBEDO: [f32; 3] = [0.8; 3];
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaterialType {
+    Diffuse,
+    Metal,
+    Glass,
+    Principled,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription {
+    #[serde(rename = "type")]
+    pub kind: MaterialType,
+    // Glass takes this as its absorption coefficient per unit of distance and
+    // defaults to clear; other materials default to a light grey.
+    pub albedo: Option<[f32; 3]>,
+    #[serde(default)]
+    pub emission: [f32; 3],
+    // GGX roughness; defaults to 0 for glass and 0.5 otherwise.
+    pub roughness: Option<f32>,
+    #[serde(default = "default_ior")]
+    pub ior: f32,
+    // Metals only; replaces albedo as the reflectance.
+    pub complex_ior: Option<ComplexIor>,
+    // Principled only, with albedo as the base color. Unset parameters take the
+    // defaults of Blender's Principled BSDF.
+    pub metallic: Option<f32>,
+    pub specular: Option<f32>,
+    pub transmission: Option<f32>,
+    pub clearcoat: Option<f32>,
+    pub clearcoat_roughness: Option<f32>,
+    pub sheen: Option<f32>,
+    pub sheen_tint: Option<f32>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComplexIor {
+    pub eta: [f32; 3],
+    pub k: [f32; 3],
+}
+
+impl MaterialDescription {
+    pub fn to_material(&self) -> Result<Material, MaterialError> {
+        let albedo = self.albedo.unwrap_or(DEFAULT_ALBEDO);
+        let material = match self.kind {
+            MaterialType::Diffuse => Material::diffuse(albedo),
+            MaterialType::Metal => {
+                let metal = Material::metal(albedo, self.roughness.unwrap_or(0.5))?;
+                match self.complex_ior {
+                    Some(ComplexIor { eta, k }) => metal.with_complex_ior(eta, k),
+                    None => metal,
+                }
+            }
+            MaterialType::Glass => {
+                Material::glass(self.albedo.unwrap_or([0.0; 3]), self.roughness.unwrap_or(0.0), self.ior)?
+            }
+            MaterialType::Principled => return self.to_principled(),
+        };
+        material.with_emission(self.emission)
+    }
+
+    fn to_principled(&self) -> Result<Material, MaterialError> {
+        let defaults = Principled::default();
+        Material::principled(&Principled {
+            base_color: self.albedo.unwrap_or(DEFAULT_ALBEDO),
+            metallic: self.metallic.unwrap_or(defaults.metallic),
+            roughness: self.roughness.unwrap_or(defaults.roughness),
+            specular: self.specular.unwrap_or(defaults.specular),
+            transmission: self.transmission.unwrap_or(defaults.transmission),
+            clearcoat: self.clearcoat.unwrap_or(defaults.clearcoat),
+            clearcoat_roughness: self.clearcoat_roughness.unwrap_or(defaults.clearcoat_roughness),
+            sheen: self.sheen.unwrap_or(defaults.sheen),
+            sheen_tint: self.sheen_tint.unwrap_or(defaults.sheen_tint),
+            ior: self.ior,
+            emission: self.emission,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Scale {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::Uniform(1.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpAxisDescription {
+    #[default]
+    Y,
+    Z,
+}
+
+// Positions, rotations and translations are in scene space, where -Y is up.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDescription {
+    pub path: PathBuf,
+    pub material: Option<String>,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    // Euler angles in degrees, applied around X, then Y, then Z.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default)]
+    pub scale: Scale,
+    #[serde(default)]
+    pub up_axis: UpAxisDescription,
+    #[serde(default)]
+    pub flip_handedness: bool,
+    #[serde(default)]
+    pub recenter: bool,
+    #[serde(default)]
+    pub normalize: bool,
+    // Degrees; only used for vertices the file gives no normal.
+    #[serde(default = "default_crease_angle")]
+    pub crease_angle: f32,
+}
+
+impl MeshDescription {
+    pub fn import_options(&self) -> ImportOptions {
+        ImportOptions {
+            scale: match self.scale {
+                Scale::Uniform(s) => [s; 3],
+                Scale::PerAxis(s) => s,
+            },
+            up_axis: match self.up_axis {
+                UpAxisDescription::Y => UpAxis::Y,
+                UpAxisDescription::Z => UpAxis::Z,
+            },
+            flip_handedness: self.flip_handedness,
+            recenter: self.recenter,
+            normalize: self.normalize,
+            crease_angle: self.crease_angle,
+        }
+    }
+}
+
+// `quad` adds a two-sided emissive parallelogram to the geometry, so it shows up
+// in the image. The other kinds are analytic lights that only illuminate.
+// Angles are in degrees.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum LightDescription {
+    Quad {
+        corner: [f32; 3],
+        edge_u: [f32; 3],
+        edge_v: [f32; 3],
+        emission: [f32; 3],
+    },
+    Point {
+        position: [f32; 3],
+        intensity: [f32; 3],
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        intensity: [f32; 3],
+        #[serde(default = "default_spot_inner_angle")]
+        inner_angle: f32,
+        #[serde(default = "default_spot_outer_angle")]
+        outer_angle: f32,
+    },
+    Directional {
+        direction: [f32; 3],
+        irradiance: [f32; 3],
+        #[serde(default)]
+        angular_radius: f32,
+    },
+    // One-sided, facing edge_u x edge_v.
+    Area {
+        corner: [f32; 3],
+        edge_u: [f32; 3],
+        edge_v: [f32; 3],
+        radiance: [f32; 3],
+    },
+}
+
+impl LightDescription {
+    // None for `Quad`, which is geometry rather than a light source.
+    pub fn source(&self) -> Option<LightSource> {
+        let source = match *self {
+            LightDescription::Quad { .. } => return None,
+            LightDescription::Point { position, intensity } => LightSource::Point { position, intensity },
+            LightDescription::Spot { position, direction, intensity, inner_angle, outer_angle } => LightSource::Spot {
+                position,
+                direction,
+                intensity,
+                inner_angle: inner_angle.to_radians(),
+                outer_angle: outer_angle.to_radians(),
+            },
+            LightDescription::Directional { direction, irradiance, angular_radius } => LightSource::Directional {
+                direction,
+                irradiance,
+                angular_radius: angular_radius.to_radians(),
+            },
+            LightDescription::Area { corner, edge_u, edge_v, radiance } => LightSource::Area {
+                corner,
+                edge_u,
+                edge_v,
+                radiance,
+            },
+        };
+        Some(source)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentDescription {
+    #[serde(default = "default_top_color")]
+    pub top_color: [f32; 3],
+    #[serde(default = "default_bottom_color")]
+    pub bottom_color: [f32; 3],
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    // Equirectangular .hdr or .exr, relative to the scene file. Replaces the
+    // gradient when set.
+    pub map: Option<PathBuf>,
+    // Degrees about the up axis.
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl Default for EnvironmentDescription {
+    fn default() -> Self {
+        EnvironmentDescription {
+            top_color: default_top_color(),
+            bottom_color: default_bottom_color(),
+            intensity: default_intensity(),
+            map: None,
+            rotation: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectionType {
+    #[default]
+    Perspective,
+    Orthographic,
+    Equirectangular,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+}
+
+// Angles are in degrees, including the vertical `fov`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub pitch: f32,
+    #[serde(default = "default_yaw")]
+    pub yaw: f32,
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    // Lens radius in scene units, 0 for a pinhole. Takes precedence over `f_stop`.
+    pub aperture: Option<f32>,
+    pub f_stop: Option<f32>,
+    pub focus_distance: Option<f32>,
+    #[serde(default)]
+    pub blades: u32,
+    #[serde(default)]
+    pub blade_rotation: f32,
+    #[serde(default)]
+    pub projection: ProjectionType,
+    // Image height in scene units for orthographic cameras. Defaults to what the
+    // field of view spans at the focus distance.
+    pub ortho_height: Option<f32>,
+}
+
+impl CameraDescription {
+    fn validate(&self) -> Result<(), SceneFileError> {
+        if let Some(aperture) = self.aperture {
+            if aperture < 0.0 {
+                return Err(SceneFileError::NegativeAperture(aperture));
+            }
+        }
+        if let Some(f_stop) = self.f_stop {
+            if f_stop <= 0.0 || f_stop.is_nan() {
+                return Err(SceneFileError::InvalidFStop(f_stop));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        let fov = self.fov.to_radians();
+        let aperture = match (self.aperture, self.f_stop) {
+            (Some(aperture), _) => aperture,
+            (None, Some(f_stop)) => Lens::aperture_for_f_stop(f_stop, fov),
+            (None, None) => 0.0,
+        };
+        let lens = Lens {
+            aperture,
+            focus_distance: self.focus_distance.unwrap_or(Lens::default().focus_distance),
+            blades: self.blades,
+            blade_rotation: self.blade_rotation,
+        };
+        let projection = match self.projection {
+            ProjectionType::Perspective => Projection::Perspective,
+            ProjectionType::Orthographic => match self.ortho_height {
+                Some(height) => Projection::Orthographic { height },
+                None => Projection::orthographic_matching(fov, lens.focus_distance),
+            },
+            ProjectionType::Equirectangular => Projection::Equirectangular,
+            ProjectionType::FisheyeEquidistant => Projection::FisheyeEquidistant,
+            ProjectionType::FisheyeEquisolid => Projection::FisheyeEquisolid,
+        };
+        CameraPose {
+            position: self.position,
+            pitch: self.pitch,
+            yaw: self.yaw,
+            fov,
+            lens,
+            projection,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderDescription {
+    pub samples: Option<u32>,
+    pub max_bounces: Option<u32>,
+    // Samples per pixel after which accumulation stops.
+    pub target_samples: Option<u32>,
+    pub tonemap: Option<Tonemap>,
+    // In stops.
+    pub exposure: Option<f32>,
+    // Color temperature in kelvin that is displayed as white.
+    pub white_balance: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: Option<CameraDescription>,
+    #[serde(default)]
+    pub render: RenderDescription,
+    #[serde(default)]
+    pub environment: EnvironmentDescription,
+    #[serde(default)]
+    pub materials: std::collections::HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub meshes: Vec<MeshDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    // Saved viewpoints, overridden slot by slot by the scene's bookmark file.
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+    // Directory the scene file lives in; mesh paths are resolved against it.
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+impl SceneDescription {
+    pub fn load(path: &str) -> Result<SceneDescription, SceneFileError> {
+        let text = std::fs::read_to_string(path)?;
+        let mut description: SceneDescription = toml::from_str(&text)?;
+        description.base_dir = Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf();
+
+        if let Some(camera) = &description.camera {
+            camera.validate()?;
+        }
+
+        for (name, material) in &description.materials {
+            material.to_material().map_err(|source| SceneFileError::InvalidMaterial {
+                name: name.clone(),
+                source,
+            })?;
+        }
+
+        for (index, light) in description.lights.iter().enumerate() {
+            if let LightDescription::Quad { emission, .. } = light {
+                Material::emissive(*emission)
+                    .map_err(|source| SceneFileError::InvalidLight { index: index + 1, source })?;
+            }
+        }
+
+        for mesh in &description.meshes {
+            if let Some(material) = &mesh.material {
+                if !description.materials.contains_key(material) {
+                    return Err(SceneFileError::UnknownMaterial {
+                        mesh: mesh.path.display().to_string(),
+                        material: material.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(description)
+    }
+
+    pub fn mesh_path(&self, mesh: &MeshDescription) -> PathBuf {
+        self.base_dir.join(&mesh.path)
+    }
+}
+
+fn default_crease_angle() -> f32 {
+    ImportOptions::default().crease_angle
+}
+
+fn default_ior() -> f32 {
+    1.5
+}
+
+fn default_top_color() -> [f32; 3] {
+    [0.529, 0.808, 0.922]
+}
+
+fn default_bottom_color() -> [f32; 3] {
+    [0.0, 0.4, 0.8]
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_spot_inner_angle() -> f32 {
+    20.0
+}
+
+fn default_spot_outer_angle() -> f32 {
+    30.0
+}
+
+fn default_yaw() -> f32 {
+    90.0
+}
+
+fn default_fov() -> f32 {
+    90.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `text` to a scene file of its own and loads it.
+    fn load(name: &str, text: &str) -> Result<SceneDescription, SceneFileError> {
+        let path = std::env::temp_dir().join(format!("scene-file-{name}-{}.toml", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let result = SceneDescription::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let err = load("unknown-field", "[camera]\nposition = [0.0, 0.0, 0.0]\nzoom = 2.0\n").unwrap_err();
+        assert!(matches!(err, SceneFileError::Parse(_)), "{err}");
+
+        let err = load("unknown-material-field", "[materials.red]\ntype = \"diffuse\"\ncolour = [1.0, 0.0, 0.0]\n")
+            .unwrap_err();
+        assert!(matches!(err, SceneFileError::Parse(_)), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_materials() {
+        let err = load("rough-metal", "[materials.rough]\ntype = \"metal\"\nroughness = 2.0\n").unwrap_err();
+        let SceneFileError::InvalidMaterial { name, source } = err else {
+            panic!("expected an invalid material, got {err}");
+        };
+        assert_eq!(name, "rough");
+        assert_eq!(source, MaterialError::OutOfRange { parameter: "roughness", value: 2.0 });
+
+        let err = load("thin-glass", "[materials.thin]\ntype = \"glass\"\nior = 0.5\n").unwrap_err();
+        assert!(
+            matches!(err, SceneFileError::InvalidMaterial { source: MaterialError::Ior(ior), .. } if ior == 0.5),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_negative_light_emission() {
+        let text = "\
+[[lights]]
+type = \"point\"
+position = [0.0, 0.0, 0.0]
+intensity = [1.0, 1.0, 1.0]
+
+[[lights]]
+type = \"quad\"
+corner = [0.0, 0.0, 0.0]
+edge_u = [1.0, 0.0, 0.0]
+edge_v = [0.0, 0.0, 1.0]
+emission = [1.0, -1.0, 1.0]
+";
+        let err = load("negative-emission", text).unwrap_err();
+        assert!(matches!(err, SceneFileError::InvalidLight { index: 2, .. }), "{err}");
+    }
+
+    #[test]
+    fn rejects_undefined_mesh_materials() {
+        let err = load("undefined-material", "[[meshes]]\npath = \"box.obj\"\nmaterial = \"missing\"\n").unwrap_err();
+        assert!(matches!(err, SceneFileError::UnknownMaterial { ref material, .. } if material == "missing"), "{err}");
+    }
+
+    #[test]
+    fn glass_defaults_to_clear() {
+        let description = load("clear-glass", "[materials.clear]\ntype = \"glass\"\n").unwrap();
+        let material = description.materials["clear"].to_material().unwrap();
+        let clear = Material::glass([0.0; 3], 0.0, default_ior()).unwrap();
+        assert_eq!(bytemuck::bytes_of(&material), bytemuck::bytes_of(&clear));
+    }
+}
@@ -2,8 +2,7 @@ use std::vec;
 use wgpu::util::DeviceExt;
 
 pub struct Textures {
-    pub texture_buffer_a: wgpu::Buffer,
-    pub texture_buffer_b: wgpu::Buffer,
+    pub accumulation_buffer: wgpu::Buffer,
     pub surface_texture_view: wgpu::TextureView,
 }
 
@@ -29,16 +28,9 @@ impl Textures {
             view_formats: &[],
         });
 
-        let texture_buffer_a = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Buffer A"),
-            contents: bytemuck::cast_slice(&blank_buffer),
-            usage: wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::COPY_DST
-                | wgpu::BufferUsages::COPY_SRC,
-        });
-
-        let texture_buffer_b = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Buffer B"),
+        // Running mean of every sample traced since the last reset, in linear HDR.
+        let accumulation_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Accumulation Buffer"),
             contents: bytemuck::cast_slice(&blank_buffer),
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST
@@ -49,9 +41,8 @@ impl Textures {
             surface_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         Textures {
-            texture_buffer_a,
-            texture_buffer_b,
+            accumulation_buffer,
             surface_texture_view,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+use crate::app::display::Tonemap;
+use crate::app::scene_file::RenderDescription;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Primary,
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+    All,
+}
+
+impl Backend {
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Backend::Primary => wgpu::Backends::PRIMARY,
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::All => wgpu::Backends::all(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "ray_tracer", version, about = "A GPU path tracer")]
+pub struct Cli {
+    #[arg(help = "Scene description (.toml); renders the built-in test scene when omitted")]
+    pub scene: Option<String>,
+
+    #[arg(long, help = "Render offline without opening a window")]
+    pub headless: bool,
+
+    #[arg(long, help = "Window width, or image width when headless")]
+    pub width: Option<u32>,
+
+    #[arg(long, help = "Window height, or image height when headless")]
+    pub height: Option<u32>,
+
+    #[arg(short, long, help = "Samples per pixel per frame, overriding the scene file")]
+    pub samples: Option<u32>,
+
+    #[arg(short = 'b', long, help = "Maximum path length, overriding the scene file")]
+    pub max_bounces: Option<u32>,
+
+    #[arg(long, help = "Stop tracing once every pixel has this many samples, overriding the scene file")]
+    pub target_samples: Option<u32>,
+
+    #[arg(long, value_enum, help = "Tonemapping operator, overriding the scene file [default: clamp]")]
+    pub tonemap: Option<Tonemap>,
+
+    #[arg(long, allow_negative_numbers = true, help = "Exposure in stops, overriding the scene file [default: 0]")]
+    pub exposure: Option<f32>,
+
+    #[arg(long, help = "Color temperature in kelvin displayed as white, overriding the scene file [default: 6500]")]
+    pub white_balance: Option<f32>,
+
+    #[arg(short, long, default_value_t = 64, help = "Number of accumulated frames to render when headless, unless a target sample count is set")]
+    pub frames: u32,
+
+    #[arg(short, long, default_value = "render.png", help = "Output image when headless; a linear .exr is written next to it")]
+    pub output: PathBuf,
+
+    #[arg(long, help = "Keyframed camera path (.toml). Headless renders it as a numbered image sequence; the viewer plays it with J and records keyframes into it with K")]
+    pub camera_path: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 30.0, help = "Frames per second of camera path image sequences")]
+    pub fps: f32,
+
+    #[arg(long, default_value_t = 0, help = "Seed for the shader's random numbers and any randomly chosen materials")]
+    pub seed: u32,
+
+    #[arg(long, value_enum, help = "Graphics API to request an adapter from [default: primary, or all when headless]")]
+    pub backend: Option<Backend>,
+
+    #[arg(long, help = "Use a software adapter (such as lavapipe) instead of a GPU")]
+    pub fallback_adapter: bool,
+
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet", help = "Log more; repeat for more detail. RUST_LOG still takes precedence")]
+    pub verbose: u8,
+
+    #[arg(short, long, help = "Only log errors")]
+    pub quiet: bool,
+}
+
+const DEFAULT_SIZE: (u32, u32) = (800, 600);
+
+impl Cli {
+    // None when neither dimension was given, so a window keeps the platform default.
+    pub fn size(&self) -> Option<winit::dpi::PhysicalSize<u32>> {
+        if self.width.is_none() && self.height.is_none() {
+            return None;
+        }
+        Some(winit::dpi::PhysicalSize::new(
+            self.width.unwrap_or(DEFAULT_SIZE.0).max(1),
+            self.height.unwrap_or(DEFAULT_SIZE.1).max(1),
+        ))
+    }
+
+    pub fn headless_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size()
+            .unwrap_or(winit::dpi::PhysicalSize::new(DEFAULT_SIZE.0, DEFAULT_SIZE.1))
+    }
+
+    pub fn render_settings(&self) -> RenderDescription {
+        RenderDescription {
+            samples: self.samples,
+            max_bounces: self.max_bounces,
+            target_samples: self.target_samples,
+            tonemap: self.tonemap,
+            exposure: self.exposure,
+            white_balance: self.white_balance,
+        }
+    }
+
+    pub fn init_logging(&self) {
+        let level = match (self.quiet, self.verbose) {
+            (true, _) => log::LevelFilter::Error,
+            (false, 0) => log::LevelFilter::Warn,
+            (false, 1) => log::LevelFilter::Info,
+            (false, 2) => log::LevelFilter::Debug,
+            (false, _) => log::LevelFilter::Trace,
+        };
+        env_logger::Builder::new()
+            .filter_level(level)
+            .parse_default_env()
+            .init();
+    }
+}
@@ -1,9 +1,20 @@
+use crate::app::display::DisplaySettings;
+use crate::app::scene_file::RenderDescription;
+use crate::cli::{Backend, Cli};
+
 pub struct StateConfigs {
     pub base_zoom: f32,
     pub speed: f32,
-    pub focal: f32,
     pub fov: f32,
+    // Degrees of rotation per unit of mouse motion.
     pub sensitivity: f32,
+    pub samples: u32,
+    pub max_bounces: u32,
+    pub target_samples: Option<u32>,
+    pub seed: u32,
+    pub backends: wgpu::Backends,
+    pub force_fallback_adapter: bool,
+    pub display: DisplaySettings,
 }
 
 impl StateConfigs {
@@ -11,9 +22,39 @@ impl StateConfigs {
         StateConfigs {
             base_zoom: 1.5,
             speed: 0.04,
-            focal: 1.0,
-            fov: 1.5708,
+            fov: std::f32::consts::FRAC_PI_2,
             sensitivity: 0.1,
+            samples: 4,
+            max_bounces: 10,
+            target_samples: None,
+            seed: 0,
+            backends: wgpu::Backends::PRIMARY,
+            force_fallback_adapter: false,
+            display: DisplaySettings::default(),
+        }
+    }
+
+    // Render settings from the command line are applied separately, after the
+    // scene file, so they take precedence over it.
+    pub fn from_cli(cli: &Cli) -> Self {
+        StateConfigs {
+            seed: cli.seed,
+            backends: cli.backend.map_or(wgpu::Backends::PRIMARY, Backend::backends),
+            force_fallback_adapter: cli.fallback_adapter,
+            ..StateConfigs::default()
+        }
+    }
+
+    pub fn apply_render_settings(&mut self, render: &RenderDescription) {
+        if let Some(samples) = render.samples {
+            self.samples = samples.max(1);
+        }
+        if let Some(max_bounces) = render.max_bounces {
+            self.max_bounces = max_bounces;
+        }
+        if let Some(target) = render.target_samples {
+            self.target_samples = Some(target);
         }
+        self.display.apply_render_settings(render);
     }
 }
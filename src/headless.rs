@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::app::camera_path::CameraPath;
+use crate::app::display::DisplaySettings;
+use crate::app::output::{write_exr, write_png};
+use crate::app::renderer::{Renderer, RendererOptions};
+use crate::app::scene_file::SceneDescription;
+use crate::config::StateConfigs;
+use crate::{Backend, Cli};
+
+// Renders `cli.frames` accumulated frames without a window or surface and writes
+// the result to disk. With a camera path, every frame of the animation at
+// `cli.fps` is rendered that way into a numbered image sequence.
+pub async fn render_headless(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let config = StateConfigs::from_cli(cli);
+    let size = cli.headless_size();
+
+    let mut renderer = Renderer::new(&RendererOptions {
+        width: size.width,
+        height: size.height,
+        // Without a surface to satisfy, any backend will do, including GL software rasterizers.
+        backends: cli.backend.map_or(wgpu::Backends::all(), Backend::backends),
+        force_fallback_adapter: config.force_fallback_adapter,
+        samples_per_frame: config.samples,
+        max_bounces: config.max_bounces,
+        seed: config.seed,
+    })
+    .await?;
+
+    match &cli.scene {
+        Some(path) => renderer.set_scene(&SceneDescription::load(path)?)?,
+        None => renderer.load_test_scene()?,
+    }
+    if let Some(samples) = cli.samples {
+        renderer.set_samples_per_frame(samples);
+    }
+    if let Some(max_bounces) = cli.max_bounces {
+        renderer.set_max_bounces(max_bounces);
+    }
+    if let Some(target) = cli.target_samples {
+        renderer.set_target_samples(Some(target));
+    }
+    let mut display = renderer.display_settings();
+    display.apply_render_settings(&cli.render_settings());
+    renderer.set_display(display);
+
+    // A target sample count takes the place of the frame count.
+    let frames = renderer
+        .target_samples()
+        .map_or(cli.frames, |target| target.div_ceil(renderer.samples_per_frame()))
+        .max(1);
+
+    let Some(camera_path_file) = &cli.camera_path else {
+        return render_image(&mut renderer, frames, &display, &cli.output);
+    };
+    let camera_path = CameraPath::load(camera_path_file)?;
+    let base = renderer.camera_pose();
+    let fps = cli.fps.max(f32::EPSILON);
+    let images = (camera_path.duration() * fps).round() as u32 + 1;
+    for index in 0..images {
+        let time = camera_path.start() + index as f32 / fps;
+        if let Some(pose) = camera_path.pose_at(time, &base) {
+            renderer.set_camera(&pose);
+        }
+        render_image(&mut renderer, frames, &display, &sequence_path(&cli.output, index))?;
+    }
+    log::info!("Rendered {images} images of {}", camera_path_file.display());
+
+    Ok(())
+}
+
+// Accumulates from scratch for the current camera and writes a PNG through the
+// display transform plus a linear EXR next to it.
+fn render_image(
+    renderer: &mut Renderer,
+    frames: u32,
+    display: &DisplaySettings,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    renderer.reset_accumulation();
+    renderer.render_frames(frames)?;
+    let pixels = renderer.read_pixels();
+
+    let (width, height) = (renderer.width(), renderer.height());
+    let displayed: Vec<[f32; 4]> = pixels.iter().map(|&p| display.apply(p)).collect();
+    write_png(output, width, height, &displayed)?;
+    let exr_path = output.with_extension("exr");
+    write_exr(&exr_path, width, height, &pixels)?;
+    log::info!(
+        "Wrote {} and {} at {} samples per pixel",
+        output.display(),
+        exr_path.display(),
+        renderer.accumulated_samples()
+    );
+    Ok(())
+}
+
+// `render.png` becomes `render-0000.png`, `render-0001.png` and so on.
+fn sequence_path(output: &Path, index: u32) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output.extension().unwrap_or("png".as_ref()).to_string_lossy();
+    output.with_file_name(format!("{stem}-{index:04}.{extension}"))
+}
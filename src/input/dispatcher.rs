@@ -1,7 +1,13 @@
 use cgmath::{InnerSpace, Vector3, Zero};
 
+use crate::app::display::{MAX_WHITE_BALANCE, MIN_WHITE_BALANCE};
+use crate::config::StateConfigs;
 use crate::input::Action;
 use crate::State;
+const MIN_APERTURE: f32 = 0.005;
+// Zooming out stops at the unzoomed field of view.
+const MIN_ZOOM: f32 = 1.0;
+
 pub struct ActionDispatcher {
     pub zoom: f32,
 }
@@ -13,21 +19,25 @@ impl ActionDispatcher {
             zoom: 0.0 
         }
     }
+    fn zoomed_fov(&self, config: &StateConfigs) -> f32 {
+        config.fov / (config.base_zoom + self.zoom).max(MIN_ZOOM)
+    }
+
     pub fn dispatch(&mut self, actions: Vec<Action>, state: &mut State) {
         let mut camera_movement: Vector3<f32> = cgmath::Vector3::zero();
         for action in actions {
             match action {
                 Action::MoveForward => {
-                    camera_movement += state.camera.camera.forward;
+                    camera_movement += state.renderer.camera().camera.forward;
                 },
                 Action::MoveBack => {
-                    camera_movement -= state.camera.camera.forward;
+                    camera_movement -= state.renderer.camera().camera.forward;
                 },
                 Action::MoveRight => {
-                    camera_movement += state.camera.camera.right;
+                    camera_movement += state.renderer.camera().camera.right;
                 },
                 Action::MoveLeft => {
-                    camera_movement -= state.camera.camera.right;
+                    camera_movement -= state.renderer.camera().camera.right;
                 },
                 Action::MoveUp => {
                     camera_movement -= cgmath::Vector3::unit_y();
@@ -42,33 +52,109 @@ impl ActionDispatcher {
                     state.input_handler.flags.camera_has_moved = true;
                 },
                 Action::Zoom => {
-                    state.camera.set_fov(state.config.fov * (state.config.base_zoom + self.zoom), &state.gpu_context.queue);
+                    let fov = self.zoomed_fov(&state.config);
+                    state.update_camera(|pose| pose.fov = fov);
                     state.input_handler.flags.is_zoomed = true;
-                    state.input_handler.flags.camera_has_moved = true;
                 }
                 Action::UnZoom => {
-                    state.camera.set_fov(state.config.fov, &state.gpu_context.queue);
+                    let fov = state.config.fov;
+                    state.update_camera(|pose| pose.fov = fov);
                     state.input_handler.flags.is_zoomed = false;
                     self.zoom = 0.0;
-                    state.input_handler.flags.camera_has_moved = true;
                 }
                 Action::ZoomIn => {
                     self.zoom += 0.01;
-                    state.camera.set_fov(state.config.fov * (state.config.base_zoom + self.zoom), &state.gpu_context.queue);
-                    state.input_handler.flags.camera_has_moved = true;
+                    let fov = self.zoomed_fov(&state.config);
+                    state.update_camera(|pose| pose.fov = fov);
                 }
                 Action::ZoomOut => {
-                    self.zoom -= 0.01;
-                    state.camera.set_fov(state.config.fov * (state.config.base_zoom + self.zoom), &state.gpu_context.queue);
-                    state.input_handler.flags.camera_has_moved = true;
+                    self.zoom = (self.zoom - 0.01).max(MIN_ZOOM - state.config.base_zoom);
+                    let fov = self.zoomed_fov(&state.config);
+                    state.update_camera(|pose| pose.fov = fov);
+                }
+                Action::Screenshot => {
+                    // Taken after the next redraw, once the frame in flight has been traced.
+                    state.screenshot_requested = true;
+                    state.needs_redraw = true;
+                }
+                // Display changes only rewrite the display uniform, so accumulation continues.
+                Action::CycleTonemap => {
+                    let mut settings = state.renderer.display_settings();
+                    settings.tonemap = settings.tonemap.next();
+                    log::info!("Tonemapping with {:?}", settings.tonemap);
+                    state.renderer.set_display(settings);
+                    state.needs_redraw = true;
+                }
+                Action::Exposure(stops) => {
+                    let mut settings = state.renderer.display_settings();
+                    settings.exposure += stops;
+                    log::info!("Exposure {:+.1} EV", settings.exposure);
+                    state.renderer.set_display(settings);
+                    state.needs_redraw = true;
+                }
+                Action::WhiteBalance(kelvin) => {
+                    let mut settings = state.renderer.display_settings();
+                    settings.white_balance = (settings.white_balance + kelvin).clamp(MIN_WHITE_BALANCE, MAX_WHITE_BALANCE);
+                    log::info!("White balance {} K", settings.white_balance);
+                    state.renderer.set_display(settings);
+                    state.needs_redraw = true;
+                }
+                Action::Focus(factor) => {
+                    let mut lens = state.renderer.camera().camera.lens;
+                    lens.focus_distance *= factor;
+                    log::debug!("Focus distance {}", lens.focus_distance);
+                    state.update_camera(|pose| pose.lens = lens);
+                }
+                Action::Aperture(factor) => {
+                    let mut lens = state.renderer.camera().camera.lens;
+                    // Opening up from a pinhole starts at the smallest aperture, and
+                    // stopping down past it returns to a pinhole.
+                    lens.aperture = if lens.aperture == 0.0 && factor > 1.0 {
+                        MIN_APERTURE
+                    } else if lens.aperture * factor < MIN_APERTURE {
+                        0.0
+                    } else {
+                        lens.aperture * factor
+                    };
+                    log::info!("Aperture radius {}", lens.aperture);
+                    state.update_camera(|pose| pose.lens = lens);
+                }
+                Action::PickFocus => state.pick_focus(),
+                Action::ToggleCursorGrab => state.surface_state.set_cursor_grab(
+                    !state.input_handler.mouse.grabbed,
+                    &mut state.input_handler.mouse,
+                ),
+                Action::RecordKeyframe => state.record_keyframe(),
+                Action::ToggleCameraPath => state.toggle_camera_path(),
+                Action::ClearCameraPath => {
+                    state.camera_path.keyframes.clear();
+                    state.playback_start = None;
+                    log::info!("Cleared camera path");
+                }
+                Action::RecallBookmark(slot) => {
+                    state.recall_bookmark(slot);
+                    if state.input_handler.flags.is_zoomed {
+                        let fov = self.zoomed_fov(&state.config);
+                        state.update_camera(|pose| pose.fov = fov);
+                    }
+                }
+                Action::StoreBookmark(slot) => state.store_bookmark(slot),
+                Action::CycleProjection => {
+                    let camera = &state.renderer.camera().camera;
+                    let projection = camera.projection.next(camera.fov, camera.lens.focus_distance);
+                    log::info!("Projection {:?}", projection);
+                    state.update_camera(|pose| pose.projection = projection);
                 }
                 Action::SetFlySpeed(speed) => {
                     state.config.speed = speed;
                 }
                 Action::Test => {
-                    state.scene.setup_test_scene(&state.gpu_context.device);
+                    if let Err(err) = state.renderer.load_test_scene() {
+                        log::error!("Failed to load test scene: {err}");
+                    }
                     
-                    state.bind_groups.rebuild_scene_bind_group(&state.gpu_context.device, &state.scene.material_buffer, &state.scene.vertex_buffer, &state.scene.tri_buffer);
+                    state.apply_scene_camera();
+                    state.load_bookmarks(None);
                     state.input_handler.flags.camera_has_moved = true;
                 }
                 _ => ()
@@ -76,9 +162,8 @@ impl ActionDispatcher {
         }
         
         if camera_movement.magnitude() > 0.0 {
-            let new_camera_pos = state.camera.camera.position + camera_movement.normalize() * state.config.speed;
-            state.camera.set_position(new_camera_pos, &state.gpu_context.queue);
-            state.input_handler.flags.camera_has_moved = true;
+            let new_camera_pos = state.renderer.camera().camera.position + camera_movement.normalize() * state.config.speed;
+            state.update_camera(|pose| pose.position = new_camera_pos.into());
         }
     }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use crate::camera::*;
+use crate::renderer::Renderer;
 use std::collections::{HashMap, HashSet};
 
 use winit::{event::*, keyboard::KeyCode};
@@ -18,6 +18,29 @@ pub enum Action {
     ZoomOut,
     Test,
     Fullscreen,
+    Screenshot,
+    CycleTonemap,
+    // Steps exposure by the given stops.
+    Exposure(f32),
+    // Steps white balance by the given kelvin.
+    WhiteBalance(f32),
+    // Scales the focus distance by the given factor.
+    Focus(f32),
+    // Scales the aperture radius by the given factor.
+    Aperture(f32),
+    // Focuses on the surface under the cursor.
+    PickFocus,
+    // Frees the cursor for picking, or hands it back to mouse look.
+    ToggleCursorGrab,
+    CycleProjection,
+    // Appends the current camera to the camera path.
+    RecordKeyframe,
+    ToggleCameraPath,
+    ClearCameraPath,
+    // Moves the camera to a bookmarked viewpoint; with Ctrl held the number keys
+    // store one instead.
+    RecallBookmark(u32),
+    StoreBookmark(u32),
     SetFlySpeed(f32),
     None,
 }
@@ -48,6 +71,11 @@ pub struct Mouse {
     pub just_clicked: HashSet<MouseButton>,
     pub just_released: HashSet<MouseButton>,
     pub held: HashSet<MouseButton>,
+    // Cursor position in physical pixels from the top left of the window.
+    pub position: Option<(f64, f64)>,
+    // While grabbed the cursor is hidden and steers the camera, so it points at the
+    // center of the window. Released, it points where it is and the camera holds still.
+    pub grabbed: bool,
 }
 
 impl Mouse {
@@ -56,8 +84,22 @@ impl Mouse {
             just_clicked: HashSet::new(),
             just_released: HashSet::new(),
             held: HashSet::new(),
+            position: None,
+            grabbed: false,
         }
     }
+
+    // Pixel the cursor points at in a window of `size`.
+    pub fn target_pixel(&self, size: winit::dpi::PhysicalSize<u32>) -> (u32, u32) {
+        match self.position {
+            Some((x, y)) if !self.grabbed => (
+                (x.max(0.0) as u32).min(size.width.saturating_sub(1)),
+                (y.max(0.0) as u32).min(size.height.saturating_sub(1)),
+            ),
+            _ => (size.width / 2, size.height / 2),
+        }
+    }
+
     fn mouse_click(&mut self, state: &ElementState, mouse_button: &MouseButton) {
         match state {
             ElementState::Pressed => {
@@ -154,6 +196,88 @@ impl InputHandler {
         bindings.insert(
             KeyCode::KeyT, 
             [Action::Test, Action::None, Action::None]);
+        bindings.insert(
+            KeyCode::KeyP,
+            [Action::Screenshot, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyM,
+            [Action::CycleTonemap, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::BracketLeft,
+            [Action::Exposure(-0.5), Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::BracketRight,
+            [Action::Exposure(0.5), Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::Comma,
+            [Action::WhiteBalance(-500.0), Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::Period,
+            [Action::WhiteBalance(500.0), Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyQ,
+            [Action::None, Action::Focus(1.0 / 1.01), Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyE,
+            [Action::None, Action::Focus(1.01), Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyF,
+            [Action::PickFocus, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::Tab,
+            [Action::ToggleCursorGrab, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyV,
+            [Action::CycleProjection, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyK,
+            [Action::RecordKeyframe, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::KeyJ,
+            [Action::ToggleCameraPath, Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::Backspace,
+            [Action::ClearCameraPath, Action::None, Action::None],
+        );
+        let digits = [
+            KeyCode::Digit0,
+            KeyCode::Digit1,
+            KeyCode::Digit2,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit5,
+            KeyCode::Digit6,
+            KeyCode::Digit7,
+            KeyCode::Digit8,
+            KeyCode::Digit9,
+        ];
+        for (slot, key) in digits.into_iter().enumerate() {
+            bindings.insert(
+                key,
+                [Action::RecallBookmark(slot as u32), Action::None, Action::None],
+            );
+        }
+        bindings.insert(
+            KeyCode::Minus,
+            [Action::Aperture(1.0 / 1.25), Action::None, Action::None],
+        );
+        bindings.insert(
+            KeyCode::Equal,
+            [Action::Aperture(1.25), Action::None, Action::None],
+        );
         bindings.insert(
             KeyCode::ControlLeft,
             [
@@ -174,8 +298,8 @@ impl InputHandler {
     pub fn process_input(
         &mut self,
         process_event: &winit::event::Event<()>,
-        camera: &mut Camera,
-        queue: &wgpu::Queue,
+        renderer: &mut Renderer,
+        sensitivity: f32,
     ) {
         match process_event {
             Event::WindowEvent { event, .. } => match event {
@@ -189,28 +313,31 @@ impl InputHandler {
                         },
                     ..
                 } => {
-                    self.keys.key_press(&state, key_code);
+                    self.keys.key_press(state, key_code);
                 }
-                WindowEvent::MouseWheel { delta, .. } => match delta {
-                    MouseScrollDelta::LineDelta(_x, y) => {
-                        if y > &0.0 {
-                            self.flags.scrolled_up = true;
-                        } else if y < &0.0 {
-                            self.flags.scrolled_down = true;
-                        }
+                WindowEvent::MouseWheel {
+                    delta: MouseScrollDelta::LineDelta(_x, y),
+                    ..
+                } => {
+                    if y > &0.0 {
+                        self.flags.scrolled_up = true;
+                    } else if y < &0.0 {
+                        self.flags.scrolled_down = true;
                     }
-                    _ => (),
-                },
+                }
                 WindowEvent::MouseInput { state, button, .. } => {
                     self.mouse.mouse_click(state, button);
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.mouse.position = Some((position.x, position.y));
+                }
                 _ => (),
             },
 
-            Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseMotion { delta } => self.mouse_move(delta, camera, queue),
-                _ => (),
-            },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if self.mouse.grabbed => self.mouse_move(delta, renderer, sensitivity),
 
             _ => (),
         }
@@ -222,9 +349,13 @@ impl InputHandler {
 
     pub fn get_actions(&mut self) -> Vec<Action> {
         let mut actions = vec![];
+        let ctrl = self.keys.held.contains(&KeyCode::ControlLeft) || self.keys.held.contains(&KeyCode::ControlRight);
         for key in &self.keys.just_pressed {
             if let Some(action) = self.get_action(key) {
-                actions.push(action[0])
+                match action[0] {
+                    Action::RecallBookmark(slot) if ctrl => actions.push(Action::StoreBookmark(slot)),
+                    pressed => actions.push(pressed),
+                }
             }
         }
         for key in &self.keys.held {
@@ -238,10 +369,14 @@ impl InputHandler {
             }
         }
 
-        if self.flags.is_zoomed == true && self.flags.scrolled_up == true {
+        if self.mouse.just_clicked.contains(&MouseButton::Left) {
+            actions.push(Action::PickFocus);
+        }
+
+        if self.flags.is_zoomed && self.flags.scrolled_up {
             actions.push(Action::ZoomIn);
         }
-        if self.flags.is_zoomed == true && self.flags.scrolled_down == true {
+        if self.flags.is_zoomed && self.flags.scrolled_down {
             actions.push(Action::ZoomOut);
         }
 
@@ -273,10 +408,11 @@ impl InputHandler {
             .insert(key, [pressed_action, held_action, released_action]);
     }
 
-    pub fn mouse_move(&mut self, delta: &(f64, f64), camera: &mut Camera, queue: &wgpu::Queue) {
-        let pitch: f32 = camera.camera.pitch.0 + delta.1 as f32 * -0.1;
-        let yaw: f32 = camera.camera.yaw.0 + delta.0 as f32 * 0.1;
-        camera.set_rotation(pitch, yaw, &queue);
+    pub fn mouse_move(&mut self, delta: &(f64, f64), renderer: &mut Renderer, sensitivity: f32) {
+        let mut pose = renderer.camera_pose();
+        pose.pitch -= delta.1 as f32 * sensitivity;
+        pose.yaw += delta.0 as f32 * sensitivity;
+        renderer.set_camera(&pose);
         self.flags.camera_has_moved = true;
     }
 }
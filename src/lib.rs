@@ -1,34 +1,43 @@
 mod app;
+mod cli;
 mod config;
+mod headless;
 mod input;
 
 use app::*;
+use app::bookmarks::bookmarks_path;
+pub use app::bookmarks::{Bookmark, BookmarkError, Bookmarks};
+pub use app::camera::{CameraPose, Lens, Projection};
+pub use app::camera_path::{CameraPath, CameraPathError, Ease, Interpolation, Keyframe};
+pub use app::display::{DisplaySettings, Tonemap};
+pub use app::gltf_import::{load_gltf, GltfScene};
+pub use app::lights::LightSource;
+pub use app::material::{Material, MaterialError, MaterialKind, Principled};
+pub use app::scene_file::SceneDescription;
+pub use app::mesh::{parse_obj, ImportOptions, ImportTransform, Mesh, ObjError, UpAxis};
+pub use app::output::{linear_to_srgb, write_exr, write_png};
+pub use app::pick::PickHit;
+pub use app::renderer::{Renderer, RendererError, RendererOptions};
+pub use cli::{Backend, Cli};
+pub use headless::render_headless;
 use config::*;
 use input::*;
 use std::{
+    path::PathBuf,
     time::{Duration, Instant},
     vec,
 };
-use wgpu::util::DeviceExt;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct FrameUniform {
-    global_frame_info: [u32; 4],
-}
-
 struct FrameInfo {
     last_frame: Instant,
     frame_accum: Duration,
     frame_count: u32,
     last_fps: Instant,
-    frame_uniform: FrameUniform,
-    frame_buffer: Option<wgpu::Buffer>,
 }
 
 struct SurfaceState<'a> {
@@ -41,12 +50,12 @@ struct SurfaceState<'a> {
 }
 
 impl<'a> SurfaceState<'a> {
-    async fn new(window: &'a Window) -> SurfaceState<'a> {
+    async fn new(window: &'a Window, state_config: &StateConfigs) -> SurfaceState<'a> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
+            backends: state_config.backends,
             ..Default::default()
         });
 
@@ -56,7 +65,7 @@ impl<'a> SurfaceState<'a> {
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
                 compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                force_fallback_adapter: state_config.force_fallback_adapter,
             })
             .await
             .unwrap();
@@ -82,16 +91,10 @@ impl<'a> SurfaceState<'a> {
             desired_maximum_frame_latency: 2,
         };
 
-        let frame_uniform = FrameUniform {
-            global_frame_info: [0; 4],
-        };
-
         let frame_info = FrameInfo {
             last_frame: Instant::now(),
             frame_accum: Duration::ZERO,
             frame_count: 0,
-            frame_uniform,
-            frame_buffer: None,
             last_fps: Instant::now(),
         };
 
@@ -105,25 +108,8 @@ impl<'a> SurfaceState<'a> {
         }
     }
 
-    fn configure_surface(&mut self, device: &wgpu::Device) {
-        self.surface.configure(&device, &self.config);
-        self.frame_info.frame_buffer = Some(device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Frame Buffer"),
-                contents: bytemuck::cast_slice(&[self.frame_info.frame_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            },
-        ));
-    }
-
-    fn update_frame_buffer(&self, queue: &wgpu::Queue) {
-        if let Some(buffer) = &self.frame_info.frame_buffer {
-            queue.write_buffer(
-                buffer,
-                0,
-                bytemuck::cast_slice(&[self.frame_info.frame_uniform]),
-            );
-        }
+    fn configure_surface(&self, device: &wgpu::Device) {
+        self.surface.configure(device, &self.config);
     }
 
     fn toggle_fullscreen(&mut self) {
@@ -134,119 +120,157 @@ impl<'a> SurfaceState<'a> {
                 .set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
         }
     }
-}
 
-struct GpuContext {
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    sampler: wgpu::Sampler,
+    fn set_cursor_grab(&self, grab: bool, mouse: &mut Mouse) {
+        let mode = if grab {
+            winit::window::CursorGrabMode::Confined
+        } else {
+            winit::window::CursorGrabMode::None
+        };
+        if let Err(err) = self.window.set_cursor_grab(mode) {
+            log::warn!("Failed to change the cursor grab: {err}");
+            return;
+        }
+        self.window.set_cursor_visible(!grab);
+        mouse.grabbed = grab;
+    }
 }
 
-impl GpuContext {
-    async fn new(adapter: &wgpu::Adapter) -> GpuContext {
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                label: None,
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-            })
-            .await
-            .unwrap();
-
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            ..Default::default()
-        });
-
-        GpuContext {
-            device,
-            queue,
-            sampler,
+// Loads the scene file if one was given, otherwise the built-in test scene.
+// Render settings from the file override the defaults in `config`, and those
+// from the command line override both.
+fn load_startup_scene(
+    renderer: &mut Renderer,
+    config: &mut StateConfigs,
+    cli: &Cli,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &cli.scene {
+        Some(path) => {
+            let description = SceneDescription::load(path)?;
+            config.apply_render_settings(&description.render);
+            renderer.set_scene(&description)?;
         }
+        None => renderer.load_test_scene()?,
     }
+    config.apply_render_settings(&cli.render_settings());
+    Ok(())
 }
 
+// A file that does not exist yet starts an empty path to record into.
+fn load_camera_path(file: &std::path::Path) -> CameraPath {
+    if !file.exists() {
+        return CameraPath::default();
+    }
+    match CameraPath::load(file) {
+        Ok(camera_path) => {
+            log::info!("Loaded {} keyframes from {}", camera_path.keyframes.len(), file.display());
+            camera_path
+        }
+        Err(err) => {
+            log::error!("Failed to load camera path: {err}");
+            CameraPath::default()
+        }
+    }
+}
 
 struct State<'a> {
     surface_state: SurfaceState<'a>,
-    gpu_context: GpuContext,
-    camera: Camera,
-    scene: Scene,
+    renderer: Renderer,
+    render_pipeline: wgpu::RenderPipeline,
     config: StateConfigs,
-    bind_groups: BindGroups,
-    textures: Textures,
-    pipelines: Pipelines,
+    camera_path: CameraPath,
+    // Where recorded keyframes are saved.
+    camera_path_file: PathBuf,
+    // When the camera path started playing, if it is.
+    playback_start: Option<Instant>,
+    // The scene file's bookmarks overridden by those saved for the scene.
+    bookmarks: Bookmarks,
+    bookmarks_file: PathBuf,
     input_handler: InputHandler,
     timestep: Duration,
     quit_flag: bool,
+    screenshot_requested: bool,
+    // Forces a redraw after the image has converged, when only the display changed.
+    needs_redraw: bool,
 }
 
 impl<'a> State<'a> {
-    async fn new(window: &'a Window) -> State<'a> {
+    async fn new(window: &'a Window, cli: &Cli) -> State<'a> {
         let quit_flag = false;
-        let config = StateConfigs::default();
-        let mut surface_state = SurfaceState::new(window).await;
-        let gpu_context = GpuContext::new(&surface_state.adapter).await;
-        surface_state.configure_surface(&gpu_context.device);
-        let camera = Camera::new(
-            &surface_state.size,
-            &gpu_context.device,
-            &gpu_context.queue,
-            config.fov,
-        );
-        let mut scene = scene::Scene::new(&gpu_context.device);
-        scene.setup_test_scene(&gpu_context.device);
-        let textures = Textures::new(&gpu_context.device, &surface_state.size);
-        let bind_groups = BindGroups::new(
-            &gpu_context.device,
-            &gpu_context.sampler,
-            &scene.vertex_buffer,
-            &scene.tri_buffer,
-            &scene.material_buffer,
-            &camera.buffer,
-            &surface_state.frame_info.frame_buffer,
-            &textures.texture_buffer_a,
-            &textures.texture_buffer_b,
-            &textures.surface_texture_view,
-        );
-        let pipelines = Pipelines::new(&gpu_context.device, &surface_state.config, &bind_groups);
+        let mut config = StateConfigs::from_cli(cli);
+        let surface_state = SurfaceState::new(window, &config).await;
+        let mut renderer = Renderer::from_adapter(
+            &surface_state.adapter,
+            &RendererOptions {
+                width: surface_state.size.width,
+                height: surface_state.size.height,
+                backends: config.backends,
+                force_fallback_adapter: config.force_fallback_adapter,
+                samples_per_frame: config.samples,
+                max_bounces: config.max_bounces,
+                seed: config.seed,
+            },
+        )
+        .await
+        .unwrap();
+        if let Err(err) = load_startup_scene(&mut renderer, &mut config, cli) {
+            log::error!("Failed to load scene: {err}");
+        }
+        renderer.set_samples_per_frame(config.samples);
+        renderer.set_max_bounces(config.max_bounces);
+        renderer.set_target_samples(config.target_samples);
+        renderer.set_display(config.display);
+        surface_state.configure_surface(renderer.device());
+        let render_pipeline =
+            create_render_pipeline(renderer.device(), surface_state.config.format, renderer.bind_groups());
+        let camera_path_file = cli.camera_path.clone().unwrap_or_else(output::recorded_camera_path);
+        let camera_path = load_camera_path(&camera_path_file);
         let input_handler = InputHandler::new_defaults();
 
-        Self {
+        let mut state = Self {
             surface_state,
-            gpu_context,
-            camera,
-            scene,
+            renderer,
+            render_pipeline,
             config,
-            bind_groups,
-            textures,
-            pipelines,
+            camera_path,
+            camera_path_file,
+            playback_start: None,
+            bookmarks: Bookmarks::default(),
+            bookmarks_file: PathBuf::new(),
             input_handler,
             timestep: Duration::from_secs_f32(1.0 / 120.0),
             quit_flag,
+            screenshot_requested: false,
+            needs_redraw: false,
+        };
+        state.apply_scene_camera();
+        state.load_bookmarks(cli.scene.as_deref());
+        state
+    }
+
+    // The renderer has already moved to the scene's camera; zooming is relative
+    // to its field of view.
+    fn apply_scene_camera(&mut self) {
+        if let Some(pose) = self.renderer.scene().camera {
+            self.config.fov = pose.fov;
         }
     }
 
+    // Applies `change` to the camera and starts a new image.
+    fn update_camera(&mut self, change: impl FnOnce(&mut CameraPose)) {
+        let mut pose = self.renderer.camera_pose();
+        change(&mut pose);
+        self.renderer.set_camera(&pose);
+        self.input_handler.flags.camera_has_moved = true;
+    }
+
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.surface_state.size = new_size;
             self.surface_state.config.width = new_size.width;
             self.surface_state.config.height = new_size.height;
-            self.surface_state
-                .surface
-                .configure(&self.gpu_context.device, &self.surface_state.config);
-
-            self.camera.camera.resize(new_size);
-            self.camera.build_uniform();
-            self.camera.update_buffer(&self.gpu_context.queue);
-            self.rebuild_texture_and_bind_groups(&new_size);
+            self.surface_state.configure_surface(self.renderer.device());
+            self.renderer.resize(new_size.width, new_size.height);
         }
     }
 
@@ -257,39 +281,15 @@ impl<'a> State<'a> {
             .create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder =
-            self.gpu_context
-                .device
+            self.renderer
+                .device()
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                     label: Some("Render Encoder"),
                 });
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Compute Pass"),
-                timestamp_writes: None,
-            });
-
-            compute_pass.set_pipeline(&self.pipelines.compute_pipeline);
-            compute_pass.set_bind_group(0, &self.bind_groups.compute_bind_group, &[]);
-            compute_pass.set_bind_group(1, &self.bind_groups.camera_bind_group, &[]);
-            compute_pass.set_bind_group(2, &self.bind_groups.scene_bind_group, &[]);
-            if self
-                .surface_state
-                .frame_info
-                .frame_uniform
-                .global_frame_info[0]
-                % 2
-                == 0
-            {
-                compute_pass.set_bind_group(3, &self.bind_groups.bind_group_a_read_b_write, &[]);
-            } else {
-                compute_pass.set_bind_group(3, &self.bind_groups.bind_group_b_read_a_write, &[])
-            }
-
-            let (w, h) = (
-                self.surface_state.size.width,
-                self.surface_state.size.height,
-            );
-            compute_pass.dispatch_workgroups((w + 7) / 8, (h + 7) / 8, 1);
+        // Once converged the accumulation is only displayed, leaving the GPU idle.
+        let trace = !self.renderer.is_converged();
+        if trace {
+            self.renderer.encode_trace(&mut encoder);
         }
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -307,36 +307,169 @@ impl<'a> State<'a> {
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.pipelines.render_pipeline);
-            render_pass.set_bind_group(0, &self.bind_groups.fragment_bind_group, &[]);
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.renderer.bind_groups().fragment_bind_group, &[]);
             render_pass.draw(0..3, 0..1);
         }
 
-        self.gpu_context
-            .queue
+        self.renderer
+            .queue()
             .submit(std::iter::once(encoder.finish()));
         output.present();
 
+        if trace {
+            self.renderer.finish_frame();
+            if self.renderer.is_converged() {
+                log::info!("Reached {} samples per pixel", self.renderer.accumulated_samples());
+            }
+        }
+
         Ok(())
     }
 
-    fn rebuild_texture_and_bind_groups(&mut self, new_size: &winit::dpi::PhysicalSize<u32>) {
-        self.textures = Textures::new(&self.gpu_context.device, new_size);
-        self.bind_groups.rebuild_compute_bind_group(
-            &self.gpu_context.device,
-            &self.surface_state.frame_info.frame_buffer,
-            &self.textures.surface_texture_view,
-        );
-        self.bind_groups.rebuild_fragment_bind_group(
-            &self.gpu_context.device,
-            &self.gpu_context.sampler,
-            &self.textures.surface_texture_view,
+    // Writes the accumulated image as a PNG through the display transform and as a
+    // linear EXR.
+    fn save_screenshot(&self) -> Result<(), image::ImageError> {
+        let samples = self.renderer.accumulated_samples();
+        if samples == 0 {
+            log::warn!("Nothing has accumulated since the camera last moved, skipping screenshot");
+            return Ok(());
+        }
+
+        let pixels = self.renderer.read_pixels();
+        let (width, height) = (self.renderer.width(), self.renderer.height());
+        let display = self.renderer.display_settings();
+
+        let path = output::screenshot_path();
+        let png_path = path.with_extension("png");
+        let exr_path = path.with_extension("exr");
+        let displayed: Vec<[f32; 4]> = pixels.iter().map(|&p| display.apply(p)).collect();
+        output::write_png(&png_path, width, height, &displayed)?;
+        output::write_exr(&exr_path, width, height, &pixels)?;
+        log::info!(
+            "Saved {} and {} at {samples} samples per pixel",
+            png_path.display(),
+            exr_path.display()
         );
-        self.bind_groups.rebuild_texture_buffer_bind_groups(
-            &self.gpu_context.device,
-            &self.textures.texture_buffer_a,
-            &self.textures.texture_buffer_b,
+        Ok(())
+    }
+
+    // Focuses the lens on the surface under the cursor, measured along the view
+    // axis since the focal plane faces the camera.
+    fn pick_focus(&mut self) {
+        let (x, y) = self.input_handler.mouse.target_pixel(self.surface_state.size);
+        let Some(hit) = self.renderer.pick(x, y) else {
+            log::info!("Nothing under pixel ({x}, {y}) to focus on");
+            return;
+        };
+
+        let camera = &self.renderer.camera().camera;
+        let offset = cgmath::Point3::from(hit.position) - camera.position;
+        let focus_distance = cgmath::InnerSpace::dot(offset, camera.forward).max(1e-4);
+        log::info!(
+            "Picked triangle {} with material {} at {:?}, {:.3} away; focusing at {:.3}",
+            hit.triangle,
+            hit.material,
+            hit.position,
+            hit.distance,
+            focus_distance
         );
+        self.update_camera(|pose| pose.lens.focus_distance = focus_distance);
+    }
+
+    // Takes the loaded scene's bookmarks plus those saved for `scene`, the scene
+    // file it was loaded from.
+    fn load_bookmarks(&mut self, scene: Option<&str>) {
+        self.bookmarks = self.renderer.scene().bookmarks.clone();
+        self.bookmarks_file = bookmarks_path(scene);
+        if !self.bookmarks_file.exists() {
+            return;
+        }
+        match Bookmarks::load(&self.bookmarks_file) {
+            Ok(saved) => self.bookmarks.merge(&saved),
+            Err(err) => log::error!("Failed to load bookmarks: {err}"),
+        }
+    }
+
+    // Saves the current viewpoint in `slot` with the unzoomed field of view. The
+    // bookmark file only holds what was saved here, so the scene file's bookmarks
+    // stay defaults.
+    fn store_bookmark(&mut self, slot: u32) {
+        let pose = CameraPose {
+            fov: self.config.fov,
+            ..self.renderer.camera_pose()
+        };
+        let bookmark = Bookmark::from_pose(slot, &pose);
+        self.bookmarks.set(bookmark);
+
+        let mut saved = if self.bookmarks_file.exists() {
+            match Bookmarks::load(&self.bookmarks_file) {
+                Ok(saved) => saved,
+                Err(err) => {
+                    log::error!("Not overwriting unreadable bookmarks: {err}");
+                    return;
+                }
+            }
+        } else {
+            Bookmarks::default()
+        };
+        saved.set(bookmark);
+        match saved.save(&self.bookmarks_file) {
+            Ok(()) => log::info!("Saved bookmark {slot} to {}", self.bookmarks_file.display()),
+            Err(err) => log::error!("Failed to save bookmarks: {err}"),
+        }
+    }
+
+    // Makes the bookmarked field of view the unzoomed one.
+    fn recall_bookmark(&mut self, slot: u32) {
+        let Some(bookmark) = self.bookmarks.get(slot) else {
+            log::info!("No bookmark in slot {slot}; save one with Ctrl+{slot}");
+            return;
+        };
+        let pose = bookmark.pose(&self.renderer.camera_pose());
+        self.config.fov = pose.fov;
+        self.update_camera(|current| *current = pose);
+        log::info!("Recalled bookmark {slot}");
+    }
+
+    // Appends the current camera to the path and saves it.
+    fn record_keyframe(&mut self) {
+        self.camera_path.record(&self.renderer.camera_pose());
+        match self.camera_path.save(&self.camera_path_file) {
+            Ok(()) => log::info!(
+                "Recorded keyframe {} into {}",
+                self.camera_path.keyframes.len(),
+                self.camera_path_file.display()
+            ),
+            Err(err) => log::error!("Failed to save camera path: {err}"),
+        }
+    }
+
+    fn toggle_camera_path(&mut self) {
+        if self.playback_start.take().is_some() {
+            log::info!("Stopped camera path");
+        } else if self.camera_path.keyframes.is_empty() {
+            log::warn!("No keyframes to play; record some with K");
+        } else {
+            log::info!("Playing camera path of {:.1} s", self.camera_path.duration());
+            self.playback_start = Some(Instant::now());
+        }
+    }
+
+    // Moves the camera along the path while it plays, stopping at its end.
+    fn advance_camera_path(&mut self) {
+        let Some(start) = self.playback_start else {
+            return;
+        };
+        let elapsed = start.elapsed().as_secs_f32();
+        let time = self.camera_path.start() + elapsed;
+        if let Some(pose) = self.camera_path.pose_at(time, &self.renderer.camera_pose()) {
+            self.update_camera(|current| *current = pose);
+        }
+        if elapsed >= self.camera_path.duration() {
+            self.playback_start = None;
+            log::info!("Finished camera path");
+        }
     }
 
     fn quit(&mut self) {
@@ -371,14 +504,21 @@ impl<'a> State<'a> {
         event: winit::event::Event<()>,
         control_flow: &winit::event_loop::EventLoopWindowTarget<()>,
     ) {
-        match event {
-            Event::WindowEvent { ref event, .. } => match event {
-                WindowEvent::CloseRequested {} => control_flow.exit(),
+        if let Event::WindowEvent { ref event, .. } = event {
+            match event {
+                WindowEvent::CloseRequested => control_flow.exit(),
 
                 WindowEvent::Resized(physical_size) => self.resize(*physical_size),
 
                 WindowEvent::RedrawRequested => match self.render() {
-                    Ok(_) => {}
+                    Ok(_) => {
+                        if self.screenshot_requested {
+                            self.screenshot_requested = false;
+                            if let Err(err) = self.save_screenshot() {
+                                log::error!("Failed to save screenshot: {err}");
+                            }
+                        }
+                    }
 
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
                         self.resize(self.surface_state.size)
@@ -394,71 +534,57 @@ impl<'a> State<'a> {
                     }
                 },
                 _ => {}
-            },
-            _ => {}
+            }
         }
     }
 }
 
-pub async fn run() {
-    env_logger::init();
-
+pub async fn run(cli: Cli) {
     let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let mut window_builder = WindowBuilder::new();
+    if let Some(size) = cli.size() {
+        window_builder = window_builder.with_inner_size(size);
+    }
+    let window = window_builder.build(&event_loop).unwrap();
 
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window, &cli).await;
     let mut dispatcher = ActionDispatcher::new();
 
-    window
-        .set_cursor_grab(winit::window::CursorGrabMode::Confined)
-        .unwrap();
-
-    window.set_cursor_visible(false);
+    state
+        .surface_state
+        .set_cursor_grab(true, &mut state.input_handler.mouse);
 
     event_loop
         .run(move |event, control_flow| {
             control_flow.set_control_flow(ControlFlow::Poll);
 
-            if state.quit_flag == true {
+            if state.quit_flag {
                 control_flow.exit()
             }
 
             state
                 .input_handler
-                .process_input(&event, &mut state.camera, &state.gpu_context.queue);
+                .process_input(&event, &mut state.renderer, state.config.sensitivity);
 
             if let Event::NewEvents(StartCause::Poll) = event {
-                state
-                    .surface_state
-                    .frame_info
-                    .frame_uniform
-                    .global_frame_info[0] += 1;
-                if !state.input_handler.flags.camera_has_moved {
-                    state
-                        .surface_state
-                        .frame_info
-                        .frame_uniform
-                        .global_frame_info[1] += 1;
-                } else {
-                    state
-                        .surface_state
-                        .frame_info
-                        .frame_uniform
-                        .global_frame_info[1] = 0;
-                }
-                state
-                    .surface_state
-                    .update_frame_buffer(&state.gpu_context.queue);
-
                 let mut logic_ticks = state.get_ticks();
                 while logic_ticks > 0 {
-                    state.input_handler.flags.camera_has_moved = false;
                     let actions = state.input_handler.get_actions();
                     dispatcher.dispatch(actions, &mut state);
 
                     logic_ticks -= 1
                 }
-                state.surface_state.window.request_redraw();
+                state.advance_camera_path();
+
+                // Covers mouse look as well as the actions above.
+                if state.input_handler.flags.camera_has_moved {
+                    state.input_handler.flags.camera_has_moved = false;
+                    state.renderer.reset_accumulation();
+                }
+                if !state.renderer.is_converged() || state.needs_redraw {
+                    state.needs_redraw = false;
+                    state.surface_state.window.request_redraw();
+                }
             };
 
             state.process_event(event, control_flow);
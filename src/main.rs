@@ -1,5 +1,16 @@
-use ray_tracer::run;
+use clap::Parser;
+use ray_tracer::{render_headless, run, Cli};
 
 fn main() {
-    pollster::block_on(run());
-}
\ No newline at end of file
+    let cli = Cli::parse();
+    cli.init_logging();
+
+    if cli.headless {
+        if let Err(err) = pollster::block_on(render_headless(&cli)) {
+            eprintln!("error: headless render failed: {err}");
+            std::process::exit(1);
+        }
+    } else {
+        pollster::block_on(run(cli));
+    }
+}
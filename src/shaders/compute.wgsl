@@ -2,6 +2,25 @@ const INF: f32 = 3.4028235e+38;
 const PI: f32 = 3.1415926535;
 const E: f32 = 2.71828;
 const DEG_TO_RAD: f32 = PI / 180;
+// The BVH builder keeps leaves within `MAX_DEPTH` = BVH_STACK_SIZE - 1 levels.
+const BVH_STACK_SIZE: u32 = 64;
+// GGX alphas below this are treated as perfectly smooth.
+const MIN_GGX_ALPHA: f32 = 1e-3;
+// Distance new rays start off the surface, relative to the hit point's magnitude.
+const RAY_OFFSET: f32 = 1e-4;
+// Smallest random number the environment map searches scale their target by. They
+// pick the first entry whose running sum reaches the target, and a target of 0
+// would also match black texels in front of it.
+const MIN_CDF_TARGET: f32 = 1e-7;
+const MATERIAL_DIFFUSE: u32 = 0;
+const MATERIAL_METAL: u32 = 1;
+const MATERIAL_GLASS: u32 = 2;
+const MATERIAL_PRINCIPLED: u32 = 3;
+const PROJECTION_PERSPECTIVE: u32 = 0;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1;
+const PROJECTION_EQUIRECTANGULAR: u32 = 2;
+const PROJECTION_FISHEYE_EQUIDISTANT: u32 = 3;
+const PROJECTION_FISHEYE_EQUISOLID: u32 = 4;
 
 
 
@@ -15,6 +34,8 @@ const DEG_TO_RAD: f32 = PI / 180;
 
 struct FrameUniform {
     frame_info: vec4<u32>,
+    // x: user seed
+    render_info: vec4<u32>,
 }
 
 struct Ray {
@@ -30,13 +51,91 @@ struct CameraUniform {
     pixel_delta_x: vec3<f32>,
     _pad2: f32,
     pixel_delta_y: vec3<f32>,
-    _pad3: f32
+    _pad3: f32,
+    // x: aperture radius, y: blade count, z: blade rotation in radians, w: focus distance
+    lens: vec4<f32>,
+    forward: vec3<f32>,
+    // One of the PROJECTION_ constants.
+    projection: u32,
+    // xy: image size in pixels, z: vertical field of view in radians
+    projection_params: vec4<f32>,
 };
 
 struct Material {
-    albedo_and_mat: vec4<f32>,
+    // Absorption coefficient for glass, base color for principled materials.
+    albedo: vec3<f32>,
+    // One of the MATERIAL_ constants, matching `MaterialKind`.
+    kind: u32,
     emission_and_roughness: vec4<f32>,
+    // x: glass IOR, xyz: metal eta when extinction.w is set
     ior: vec4<f32>,
+    // xyz: metal extinction coefficient, w: 1 to use the complex IOR
+    extinction: vec4<f32>,
+    // x: metallic, y: specular, z: transmission, w: clearcoat
+    principled: vec4<f32>,
+    // x: sheen, y: sheen tint, z: clearcoat roughness
+    sheen_and_clearcoat: vec4<f32>,
+    // Texture index plus one for albedo, roughness, metallic and normal map; 0 for none.
+    textures: vec4<u32>,
+}
+
+struct VertexAttributes {
+    // Zero when the mesh has no vertex normals.
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+}
+
+struct Environment {
+    top_color_and_intensity: vec4<f32>,
+    // w: map rotation in radians
+    bottom_color_and_rotation: vec4<f32>,
+    // x, y: map size, z: 1 when a map is bound
+    map_info: vec4<u32>,
+}
+
+struct BvhNode {
+    aabb_min: vec3<f32>,
+    left_or_first: u32,
+    aabb_max: vec3<f32>,
+    tri_count: u32,
+}
+
+// kind 0: emissive triangle, 1: point, 2: spot, 3: directional, 4: area
+struct Light {
+    kind: u32,
+    tri: u32,
+    // Running sum of `power`; the last entry holds the total.
+    cdf: f32,
+    power: f32,
+    position: vec3<f32>,
+    cos_outer: f32,
+    direction: vec3<f32>,
+    cos_inner: f32,
+    edge_u: vec3<f32>,
+    area: f32,
+    edge_v: vec3<f32>,
+    _pad0: f32,
+    emission: vec3<f32>,
+    _pad1: f32,
+}
+
+struct LightSample {
+    direction: vec3<f32>,
+    distance: f32,
+    emission: vec3<f32>,
+    // Solid angle density, 0 when no light could be sampled. Lights that rays
+    // cannot hit report the probability of picking them instead.
+    pdf: f32,
+    // Whether BSDF sampling can also find this light, so the sample needs MIS.
+    mis: bool,
+}
+
+struct BsdfSample {
+    direction: vec3<f32>,
+    // BSDF times cosine over pdf.
+    weight: vec3<f32>,
+    // 0 for perfectly specular samples, which light sampling cannot produce.
+    pdf: f32,
 }
 
 struct GlassRefract {
@@ -48,8 +147,14 @@ struct HitInfo {
     hit: bool,
     front: bool,
     t: f32,
+    // Textures already applied.
     material: Material,
+    // Shading normal, facing the incoming ray.
     normal: vec3<f32>,
+    // Face normal, facing the incoming ray.
+    geometric_normal: vec3<f32>,
+    // Index into `tri_buffer`.
+    triangle: u32,
 }
 
 
@@ -74,11 +179,40 @@ var<storage, read> material_buffer: array<Material>;
 var<storage, read> vertex_buffer: array<vec4<f32>>;
 @group(2) @binding(2)
 var<storage, read> tri_buffer: array<vec4<u32>>;
-
+@group(2) @binding(3)
+var<storage, read> bvh_buffer: array<BvhNode>;
+@group(2) @binding(4)
+var<uniform> environment: Environment;
+@group(2) @binding(5)
+var<storage, read> light_buffer: array<Light>;
+// width * height texels (radiance, running row weight), then height running row totals.
+@group(2) @binding(6)
+var<storage, read> environment_map: array<vec4<f32>>;
+@group(2) @binding(7)
+var<storage, read> vertex_attributes: array<VertexAttributes>;
+// Four words per texture (first texel, width, height, 1 when sRGB), then the
+// texels of every texture as packed RGBA8.
+@group(2) @binding(8)
+var<storage, read> texture_buffer: array<u32>;
+
+// Mean of the `frame_info.y` samples traced per pixel since the last reset.
 @group(3) @binding(0)
-var<storage, read> read_frame_buffer: array<vec4<f32>>;
-@group(3) @ binding(1)
-var<storage, read_write> write_frame_buffer: array<vec4<f32>>;
+var<storage, read_write> accumulation: array<vec4<f32>>;
+
+struct PickResult {
+    position: vec3<f32>,
+    distance: f32,
+    triangle: u32,
+    material: u32,
+    hit: u32,
+    _pad: u32,
+}
+
+// Group 3 of the `pick` entry point. xy: pixel to pick.
+@group(3) @binding(1)
+var<uniform> pick_request: vec4<u32>;
+@group(3) @binding(2)
+var<storage, read_write> pick_result: PickResult;
 
 
 
@@ -93,73 +227,197 @@ fn raytrace(
     @builtin(global_invocation_id) id: vec3<u32>
 ) {
     let frame_count = frame.frame_info.x;
-    let pixel_center = camera.lower_left_pixel + (f32(id.x) * camera.pixel_delta_x) + (f32(id.y) * camera.pixel_delta_y);
+    let max_bounces = frame.frame_info.z;
+    let samples = max(frame.frame_info.w, 1u);
     var pixel_color = vec3<f32>(0.0);
-    let top_color = vec3<f32>(0.529, 0.808, 0.922);
-    let bottom_color = vec3<f32>(0.0, 0.4, 0.8);
-    var seed = id.x * 1973u ^ id.y * 9277u ^ frame_count * 26699u;
+    // Chance of sampling the environment map rather than an emissive triangle.
+    let environment_prob = environment_sample_probability();
+    var seed = id.x * 1973u ^ id.y * 9277u ^ frame_count * 26699u ^ pcg_randu32(frame.render_info.x);
     let texture_size = textureDimensions(traced_image);
+    // The dispatch rounds up to whole workgroups; the extra invocations would
+    // write into neighboring pixels of the accumulation buffer.
+    if id.x >= texture_size.x || id.y >= texture_size.y {
+        return;
+    }
     let buffer_pixel = id.y * texture_size.x + id.x;
 
-    for (var samples: u32 = 0; samples < 1; samples++) {
-        var sample_color = vec3<f32>(0.0);
-        seed = seed ^ samples * 5892379u;
-
-        for (var jitter: u32 = 0; jitter < 4; jitter++) {
-            seed = seed ^ jitter * 374761393u;
-            let quad_x = f32(jitter & 1u);
-            let quad_y = f32(jitter >> 1u);
-            let rand_x = pcg_randf32(seed);
-            let rand_y = pcg_randf32(seed ^ 0x85ebca6bu);
-
-            var new_pixel_center = pixel_center + (rand_x - quad_x) * 0.5 * camera.pixel_delta_x;
-            new_pixel_center = new_pixel_center + (rand_y - quad_y) * 0.5 * camera.pixel_delta_y;
-            var ray = trace_ray(camera.position, new_pixel_center);
-            var bounce_color = vec3<f32>(1.0);
-
-            for (var bounces: u32 = 0; bounces < 10; bounces++) {
-                let hit = intersect(ray);
-
-                if hit.hit == true {
-                    seed = seed ^ bounces * 374761393u;
-
-                    if length(hit.material.emission_and_roughness.xyz) > 0.0001 {
-                        sample_color += bounce_color * hit.material.emission_and_roughness.xyz;
+    // Samples are jittered within the four quadrants of the pixel in turn.
+    for (var sample: u32 = 0; sample < samples; sample++) {
+        seed = seed ^ sample * 374761393u;
+        let quad_x = f32(sample & 1u);
+        let quad_y = f32((sample >> 1u) & 1u);
+        let rand_x = pcg_randf32(seed);
+        let rand_y = pcg_randf32(seed ^ 0x85ebca6bu);
+
+        let pixel = vec2<f32>(f32(id.x) + (rand_x - quad_x) * 0.5, f32(id.y) + (rand_y - quad_y) * 0.5);
+        let lens_point = sample_aperture(pcg_randf32(seed ^ 0x27d4eb2du), pcg_randf32(seed ^ 0x165667b1u));
+        var ray = camera_ray(pixel, lens_point);
+        // Outside the image circle of a fisheye.
+        if all(ray.direction == vec3<f32>(0.0)) {
+            continue;
+        }
+        var bounce_color = vec3<f32>(1.0);
+        // Density of the BSDF sample that produced `ray`, or 0 when light sampling
+        // could not have produced it (camera rays, glass).
+        var bsdf_pdf = 0.0;
+
+        for (var bounces: u32 = 0; bounces < max_bounces; bounces++) {
+            let hit = intersect(ray);
+
+            if hit.hit == true {
+                seed = seed ^ bounces * 374761393u;
+
+                let emission = hit.material.emission_and_roughness.xyz;
+                if length(emission) > 0.0001 {
+                    var weight = 1.0;
+                    if bsdf_pdf > 0.0 {
+                        let cos_light = abs(dot(hit.geometric_normal, ray.direction));
+                        weight = power_heuristic(bsdf_pdf, light_pdf(emission, hit.t, cos_light) * (1.0 - environment_prob));
                     }
+                    pixel_color += bounce_color * emission * weight;
+                }
 
-                    var intersection = ray.origin + ray.direction * hit.t;
-                    ray.origin = intersection;
-
-                    if hit.material.albedo_and_mat.w == 0 {
-                        ray.direction = normalize(diffuse_bounce(hit.material, ray, hit.normal, seed));
-                        bounce_color *= hit.material.albedo_and_mat.xyz;
-                    } else if hit.material.albedo_and_mat.w == 1 {
-                        ray.direction = normalize(metallic_bounce(hit.material, ray, hit.normal, seed));
-                        bounce_color *= hit.material.albedo_and_mat.xyz;
-                    } else if hit.material.albedo_and_mat.w == 2 {
-                        let refraction = transparent_material(hit.material, ray, hit.normal, seed, hit.t, hit.front);
-                        ray.direction = normalize(refraction.direction);
-                        bounce_color *= refraction.attenuation;
+                var intersection = ray.origin + ray.direction * hit.t;
+                let in_direction = ray.direction;
+
+                // Next event estimation, skipped on the last bounce since a BSDF
+                // sample from here could not reach a light either.
+                if hit.material.kind != MATERIAL_GLASS && bounces + 1u < max_bounces {
+                    let light = sample_direct(intersection, pcg_randu32(seed ^ 0x68bc21ebu), environment_prob);
+                    if light.pdf > 0.0 {
+                        let bsdf = eval_bsdf(hit.material, in_direction, hit.normal, light.direction);
+                        let above = dot(light.direction, hit.geometric_normal) > 0.0;
+                        let shadow_ray = Ray(offset_origin(intersection, hit.geometric_normal, light.direction), light.direction);
+                        if bsdf.w > 0.0 && above && !occluded(shadow_ray, light.distance * 0.999) {
+                            let weight = select(1.0, power_heuristic(light.pdf, bsdf.w), light.mis);
+                            pixel_color += bounce_color * bsdf.xyz * light.emission * weight / light.pdf;
+                        }
                     }
-                    
-                } else {
-//                    bounce_color *= vec3<f32>(0.0);
-                    pixel_color += bounce_color * mix(bottom_color, top_color, (1.0 + ray.direction.y) * 0.5);
+                }
+
+                if hit.material.kind == MATERIAL_DIFFUSE {
+                    ray.direction = normalize(diffuse_bounce(hit.material, ray, hit.normal, seed));
+                    bounce_color *= hit.material.albedo;
+                    bsdf_pdf = eval_bsdf(hit.material, in_direction, hit.normal, ray.direction).w;
+                } else if hit.material.kind == MATERIAL_METAL {
+                    let sample = sample_metal(hit.material, in_direction, hit.normal, seed);
+                    ray.direction = normalize(sample.direction);
+                    bounce_color *= sample.weight;
+                    bsdf_pdf = sample.pdf;
+                } else if hit.material.kind == MATERIAL_GLASS {
+                    let refraction = transparent_material(hit.material, ray, hit.normal, seed, hit.t, hit.front);
+                    ray.direction = normalize(refraction.direction);
+                    bounce_color *= refraction.attenuation;
+                    bsdf_pdf = 0.0;
+                } else if hit.material.kind == MATERIAL_PRINCIPLED {
+                    let sample = sample_principled(hit.material, ray, hit.normal, seed, hit.t, hit.front);
+                    ray.direction = normalize(sample.direction);
+                    bounce_color *= sample.weight;
+                    bsdf_pdf = sample.pdf;
+                }
+
+                // Microfacet samples that end up below the surface are absorbed, as are
+                // reflections the shading normal bends below the face.
+                let reflected = hit.material.kind == MATERIAL_DIFFUSE || hit.material.kind == MATERIAL_METAL
+                    || (hit.material.kind == MATERIAL_PRINCIPLED && bsdf_pdf > 0.0);
+                if all(bounce_color <= vec3<f32>(0.0)) || (reflected && dot(ray.direction, hit.geometric_normal) <= 0.0) {
                     break;
                 }
+                ray.origin = offset_origin(intersection, hit.geometric_normal, ray.direction);
+
+            } else {
+                var weight = 1.0;
+                if bsdf_pdf > 0.0 && environment_prob > 0.0 {
+                    weight = power_heuristic(bsdf_pdf, environment_pdf(ray.direction) * environment_prob);
+                }
+                pixel_color += bounce_color * environment_radiance(ray.direction) * weight;
+                break;
             }
         }
-        pixel_color += sample_color;
     }
-    pixel_color = clamp(pixel_color / 4.0, vec3<f32>(0.0), vec3<f32>(1.0));
-    if frame.frame_info.y > 9 {
-        pixel_color += read_frame_buffer[buffer_pixel].xyz * (f32(frame.frame_info.y) - 10.0);
-        pixel_color /= f32(frame.frame_info.y) - 9.0;
-        write_frame_buffer[buffer_pixel] = vec4<f32>(pixel_color, 1.0);
+    // Kept linear and unbounded; the display transform in raster.wgsl maps it to 0..=1.
+    // The running mean weighs this frame's samples against those already accumulated.
+    let previous_samples = f32(frame.frame_info.y);
+    let total_samples = previous_samples + f32(samples);
+    pixel_color = max(pixel_color, vec3<f32>(0.0)) / total_samples;
+    if frame.frame_info.y > 0u {
+        pixel_color += accumulation[buffer_pixel].xyz * (previous_samples / total_samples);
     }
+    accumulation[buffer_pixel] = vec4<f32>(pixel_color, 1.0);
     textureStore(traced_image, vec2<i32>(id.xy), vec4<f32>(pixel_color, 1.0));
 }
 
+// Traces the ray through the center of one pixel from the center of the lens
+// and reports the first surface it hits.
+@compute @workgroup_size(1)
+fn pick() {
+    let ray = camera_ray(vec2<f32>(pick_request.xy), vec2<f32>(0.0));
+    var result: PickResult;
+    if all(ray.direction == vec3<f32>(0.0)) {
+        pick_result = result;
+        return;
+    }
+    let hit = intersect(ray);
+
+    if hit.hit {
+        result.position = ray.origin + ray.direction * hit.t;
+        result.distance = hit.t;
+        result.triangle = hit.triangle;
+        result.material = tri_buffer[hit.triangle].w;
+        result.hit = 1u;
+    }
+    pick_result = result;
+}
+
+// Ray through `pixel`, whose center is at whole coordinates counted from the top
+// left, leaving the lens at `lens_point` on the unit aperture. Rays outside the
+// image circle of a fisheye have a zero direction.
+fn camera_ray(pixel: vec2<f32>, lens_point: vec2<f32>) -> Ray {
+    let right = normalize(camera.pixel_delta_x);
+    let up = -normalize(camera.pixel_delta_y);
+    let image_size = camera.projection_params.xy;
+
+    switch camera.projection {
+        case PROJECTION_EQUIRECTANGULAR: {
+            let uv = (pixel + 0.5) / image_size;
+            let longitude = (uv.x - 0.5) * 2.0 * PI;
+            let latitude = (0.5 - uv.y) * PI;
+            let direction = cos(latitude) * (sin(longitude) * right + cos(longitude) * camera.forward) + sin(latitude) * up;
+            return Ray(camera.position, direction);
+        }
+        case PROJECTION_FISHEYE_EQUIDISTANT, PROJECTION_FISHEYE_EQUISOLID: {
+            // In units of half the image height, with y pointing down.
+            let offset = (pixel + 0.5 - image_size * 0.5) / (image_size.y * 0.5);
+            let r = length(offset);
+            if r > 1.0 {
+                return Ray(camera.position, vec3<f32>(0.0));
+            }
+            let half_fov = camera.projection_params.z * 0.5;
+            var theta = r * half_fov;
+            if camera.projection == PROJECTION_FISHEYE_EQUISOLID {
+                theta = 2.0 * asin(min(r * sin(half_fov * 0.5), 1.0));
+            }
+            let radial = select(vec2<f32>(0.0), offset / r, r > 0.0);
+            let direction = sin(theta) * (radial.x * right - radial.y * up) + cos(theta) * camera.forward;
+            return Ray(camera.position, direction);
+        }
+        default: {
+            // Perspective pixels lie on the focal plane and orthographic ones on the
+            // plane through the camera, so only points off the focal plane blur.
+            let plane_point = camera.lower_left_pixel + pixel.x * camera.pixel_delta_x + pixel.y * camera.pixel_delta_y;
+            var origin = camera.position;
+            var focus_point = plane_point;
+            if camera.projection == PROJECTION_ORTHOGRAPHIC {
+                origin = plane_point;
+                focus_point = plane_point + camera.forward * camera.lens.w;
+            }
+            let lens_offset = lens_point * camera.lens.x;
+            origin += lens_offset.x * right + lens_offset.y * up;
+            return trace_ray(origin, focus_point);
+        }
+    }
+}
+
 fn trace_ray(origin: vec3<f32>, point: vec3<f32>) -> Ray {
     let direction = normalize(point - origin);
     var ray: Ray;
@@ -168,51 +426,104 @@ fn trace_ray(origin: vec3<f32>, point: vec3<f32>) -> Ray {
     return ray;
 }
 
+fn intersect_tri(ray: Ray, tri: vec4<u32>) -> vec3<f32> {
+    let e1 = vertex_buffer[tri.y].xyz - vertex_buffer[tri.x].xyz;
+    let e2 = vertex_buffer[tri.z].xyz - vertex_buffer[tri.x].xyz;
+    let p_vec = cross(ray.direction, e2);
+    let d = dot(e1, p_vec);
+
+    if d < 0.0001 && d > -0.0001 {return vec3<f32>(-1.0);}
+
+    let inv_d = 1.0 / d;
+    let t_vec = ray.origin - vertex_buffer[tri.x].xyz;
+    let u = dot(t_vec, p_vec) * inv_d;
+
+    if u < 0 || u > 1 {return vec3<f32>(-1.0);}
+
+    let q_vec = cross(t_vec, e1);
+    let v = dot(ray.direction, q_vec) * inv_d;
+
+    if v < 0 || u + v > 1 {return vec3<f32>(-1.0);}
+
+    return vec3<f32>(dot(e2, q_vec) * inv_d, u, v);
+}
+
+fn intersect_aabb(ray: Ray, inv_dir: vec3<f32>, node: BvhNode, t_max: f32) -> f32 {
+    let t0 = (node.aabb_min - ray.origin) * inv_dir;
+    let t1 = (node.aabb_max - ray.origin) * inv_dir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+
+    if t_near > t_far || t_far < 0.0 || t_near > t_max {
+        return INF;
+    }
+    return t_near;
+}
+
 fn intersect(ray: Ray) -> HitInfo {
-    var t = -1.0;
     var new_t = INF;
-    var pointer: i32 = -1;
-    var n: vec3<f32>;
     var final_tri: vec4<u32>;
+    var final_index: u32;
+    var final_barycentric: vec2<f32>;
 
-    for (var i: u32 = 0; i < arrayLength(&tri_buffer); i++) {
-        let tri = tri_buffer[i];
-        let e1 = vertex_buffer[tri.y].xyz - vertex_buffer[tri.x].xyz;
-        let e2 = vertex_buffer[tri.z].xyz - vertex_buffer[tri.x].xyz;
-        let p_vec = cross(ray.direction, e2);
-        let d = dot(e1, p_vec);
+    let safe_dir = select(ray.direction, vec3<f32>(1e-8), abs(ray.direction) < vec3<f32>(1e-8));
+    let inv_dir = 1.0 / safe_dir;
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_ptr: u32 = 0;
 
-        if d < 0.0001 && d > -0.0001 {continue;}
-
-        let inv_d = 1.0 / d;
-        let t_vec = ray.origin - vertex_buffer[tri.x].xyz;
-        let u = dot(t_vec, p_vec) * inv_d;
+    if intersect_aabb(ray, inv_dir, bvh_buffer[0], INF) < INF {
+        stack[0] = 0u;
+        stack_ptr = 1u;
+    }
 
-        if u < 0 || u > 1 {continue;}
+    while stack_ptr > 0 {
+        stack_ptr--;
+        let node = bvh_buffer[stack[stack_ptr]];
 
-        let q_vec = cross(t_vec, e1);
-        let v = dot(ray.direction, q_vec) * inv_d;
+        if node.tri_count > 0 {
+            for (var i = node.left_or_first; i < node.left_or_first + node.tri_count; i++) {
+                let tri = tri_buffer[i];
+                let tri_hit = intersect_tri(ray, tri);
 
-        if v < 0 || u + v > 1 {continue;}
+                if tri_hit.x > 0.0001 && tri_hit.x < new_t {
+                    new_t = tri_hit.x;
+                    final_tri = tri;
+                    final_index = i;
+                    final_barycentric = tri_hit.yz;
+                }
+            }
+            continue;
+        }
 
-        t = dot(e2, q_vec) * inv_d;
+        var near = node.left_or_first;
+        var far = node.left_or_first + 1;
+        var t_near = intersect_aabb(ray, inv_dir, bvh_buffer[near], new_t);
+        var t_far = intersect_aabb(ray, inv_dir, bvh_buffer[far], new_t);
+
+        if t_far < t_near {
+            let tmp_index = near;
+            near = far;
+            far = tmp_index;
+            let tmp_t = t_near;
+            t_near = t_far;
+            t_far = tmp_t;
+        }
 
-        if t > 0.0001 && t < new_t {
-            new_t = t;
-            n = cross(e2, e1);
-            final_tri = tri;
+        // Push the far child first so the near one is popped next.
+        if t_far < INF && stack_ptr < BVH_STACK_SIZE {
+            stack[stack_ptr] = far;
+            stack_ptr++;
+        }
+        if t_near < INF && stack_ptr < BVH_STACK_SIZE {
+            stack[stack_ptr] = near;
+            stack_ptr++;
         }
     }
 
     if new_t < INF {
-        let front = dot(n, ray.direction) < 0;
-        return HitInfo(
-            true,
-            front,
-            new_t,
-            material_buffer[final_tri.w],
-            select(-n, n, front),
-        );
+        var hit = surface_hit(ray, final_tri, final_barycentric, new_t);
+        hit.triangle = final_index;
+        return hit;
     }
 
     return HitInfo(
@@ -221,9 +532,497 @@ fn intersect(ray: Ray) -> HitInfo {
         INF,
         material_buffer[0],
         vec3<f32>(0.0),
+        vec3<f32>(0.0),
+        0u,
     );
 }
 
+// Moves `point` off the surface with face normal `normal`, to the side `direction`
+// leaves towards, so the new ray cannot hit the same surface again.
+fn offset_origin(point: vec3<f32>, normal: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    let magnitude = max(1.0, max(abs(point.x), max(abs(point.y), abs(point.z))));
+    let offset = normal * RAY_OFFSET * magnitude;
+    return select(point - offset, point + offset, dot(direction, normal) > 0.0);
+}
+
+// Interpolates the vertex attributes of `tri` at `barycentric` (the weights of its
+// second and third vertex), applies the material's textures and picks the
+// shading normal.
+fn surface_hit(ray: Ray, tri: vec4<u32>, barycentric: vec2<f32>, t: f32) -> HitInfo {
+    let e1 = vertex_buffer[tri.y].xyz - vertex_buffer[tri.x].xyz;
+    let e2 = vertex_buffer[tri.z].xyz - vertex_buffer[tri.x].xyz;
+    let n = normalize(cross(e1, e2));
+    let front = dot(n, ray.direction) < 0;
+
+    let a0 = vertex_attributes[tri.x];
+    let a1 = vertex_attributes[tri.y];
+    let a2 = vertex_attributes[tri.z];
+    let weights = vec3<f32>(1.0 - barycentric.x - barycentric.y, barycentric.x, barycentric.y);
+    let uv = a0.uv * weights.x + a1.uv * weights.y + a2.uv * weights.z;
+
+    var material = material_buffer[tri.w];
+    if material.kind != MATERIAL_GLASS {
+        material.albedo *= sample_texture(material.textures.x, uv, vec4<f32>(1.0)).rgb;
+    }
+    material.emission_and_roughness.w *= sample_texture(material.textures.y, uv, vec4<f32>(1.0)).g;
+    material.principled.x *= sample_texture(material.textures.z, uv, vec4<f32>(1.0)).b;
+
+    var shading = a0.normal * weights.x + a1.normal * weights.y + a2.normal * weights.z;
+    shading = select(n, normalize(shading), dot(shading, shading) > 1e-12);
+    shading = select(-shading, shading, dot(shading, n) >= 0.0);
+    if material.textures.w != 0u {
+        shading = normal_mapped(material.textures.w, uv, a1.uv - a0.uv, a2.uv - a0.uv, e1, e2, shading);
+    }
+
+    let geometric_normal = select(-n, n, front);
+    var normal = select(-shading, shading, front);
+    // A shading normal facing away from the ray would send bounces through the surface.
+    if dot(normal, ray.direction) >= 0.0 {
+        normal = geometric_normal;
+    }
+
+    return HitInfo(true, front, t, material, normal, geometric_normal, 0u);
+}
+
+// Tilts `normal` by a tangent space normal map. The tangent follows u across the
+// triangle with edges `e1` and `e2` and UV deltas `duv1` and `duv2`; the map's
+// +Y points up the image, against v.
+fn normal_mapped(texture: u32, uv: vec2<f32>, duv1: vec2<f32>, duv2: vec2<f32>, e1: vec3<f32>, e2: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let det = duv1.x * duv2.y - duv2.x * duv1.y;
+    if abs(det) < 1e-12 {
+        return normal;
+    }
+    let dpdu = (e1 * duv2.y - e2 * duv1.y) / det;
+    let dpdv = (e2 * duv1.x - e1 * duv2.x) / det;
+
+    let tangent = dpdu - normal * dot(normal, dpdu);
+    if dot(tangent, tangent) < 1e-12 {
+        return normal;
+    }
+    let t = normalize(tangent);
+    var b = cross(normal, t);
+    if dot(b, dpdv) > 0.0 {
+        b = -b;
+    }
+
+    let mapped = sample_texture(texture, uv, vec4<f32>(0.5, 0.5, 1.0, 1.0)).xyz * 2.0 - 1.0;
+    return normalize(t * mapped.x + b * mapped.y + normal * mapped.z);
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    return select(pow((color + 0.055) / 1.055, vec3<f32>(2.4)), color / 12.92, color <= vec3<f32>(0.04045));
+}
+
+fn texel(first: u32, size: vec2<u32>, x: i32, y: i32, srgb: bool) -> vec4<f32> {
+    let wrapped = vec2<u32>((vec2<i32>(x, y) + vec2<i32>(size)) % vec2<i32>(size));
+    let color = unpack4x8unorm(texture_buffer[first + wrapped.y * size.x + wrapped.x]);
+    if srgb {
+        return vec4<f32>(srgb_to_linear(color.rgb), color.a);
+    }
+    return color;
+}
+
+// Bilinear lookup with repeat wrapping. `texture` is a material texture slot, and
+// `fallback` is returned when it is empty or its header is out of reach.
+fn sample_texture(texture: u32, uv: vec2<f32>, fallback: vec4<f32>) -> vec4<f32> {
+    let header = (texture - 1u) * 4u;
+    if texture == 0u || header + 3u >= arrayLength(&texture_buffer) {
+        return fallback;
+    }
+    let first = texture_buffer[header];
+    let size = vec2<u32>(texture_buffer[header + 1u], texture_buffer[header + 2u]);
+    let srgb = texture_buffer[header + 3u] != 0u;
+    if size.x == 0u || size.y == 0u {
+        return fallback;
+    }
+
+    let position = fract(uv) * vec2<f32>(size) - 0.5;
+    let base = floor(position);
+    let f = position - base;
+    let x = i32(base.x);
+    let y = i32(base.y);
+    let top = mix(texel(first, size, x, y, srgb), texel(first, size, x + 1, y, srgb), f.x);
+    let bottom = mix(texel(first, size, x, y + 1, srgb), texel(first, size, x + 1, y + 1, srgb), f.x);
+    return mix(top, bottom, f.y);
+}
+
+// Any-hit traversal for shadow rays.
+fn occluded(ray: Ray, max_t: f32) -> bool {
+    let safe_dir = select(ray.direction, vec3<f32>(1e-8), abs(ray.direction) < vec3<f32>(1e-8));
+    let inv_dir = 1.0 / safe_dir;
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_ptr: u32 = 0;
+
+    if intersect_aabb(ray, inv_dir, bvh_buffer[0], max_t) < INF {
+        stack[0] = 0u;
+        stack_ptr = 1u;
+    }
+
+    while stack_ptr > 0 {
+        stack_ptr--;
+        let node = bvh_buffer[stack[stack_ptr]];
+
+        if node.tri_count > 0 {
+            for (var i = node.left_or_first; i < node.left_or_first + node.tri_count; i++) {
+                let t = intersect_tri(ray, tri_buffer[i]).x;
+                if t > 0.0001 && t < max_t {
+                    return true;
+                }
+            }
+            continue;
+        }
+
+        for (var child = node.left_or_first; child < node.left_or_first + 2u; child++) {
+            if intersect_aabb(ray, inv_dir, bvh_buffer[child], max_t) < INF && stack_ptr < BVH_STACK_SIZE {
+                stack[stack_ptr] = child;
+                stack_ptr++;
+            }
+        }
+    }
+
+    return false;
+}
+
+// Must match `luminance` in scene.rs, which builds the light table from it.
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn total_light_power() -> f32 {
+    return light_buffer[arrayLength(&light_buffer) - 1u].cdf;
+}
+
+// Picks a light in proportion to its power, then a point or direction on it.
+fn sample_light(origin: vec3<f32>, rng_seed: u32) -> LightSample {
+    var result: LightSample;
+    result.pdf = 0.0;
+
+    let total = total_light_power();
+    if total <= 0.0 {
+        return result;
+    }
+
+    let target_power = pcg_randf32(rng_seed) * total;
+    var low = 0u;
+    var high = arrayLength(&light_buffer) - 1u;
+    while low < high {
+        let mid = (low + high) / 2u;
+        if light_buffer[mid].cdf < target_power {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+
+    let light = light_buffer[low];
+    switch light.kind {
+        case 0u: {
+            result = sample_triangle_light(light, origin, rng_seed);
+        }
+        case 1u, 2u: {
+            result = sample_point_light(light, origin);
+        }
+        case 3u: {
+            result = sample_directional_light(light, rng_seed);
+        }
+        case 4u: {
+            result = sample_area_light(light, origin, rng_seed);
+        }
+        default: {}
+    }
+    result.pdf *= light.power / total;
+    return result;
+}
+
+fn sample_triangle_light(light: Light, origin: vec3<f32>, rng_seed: u32) -> LightSample {
+    var result: LightSample;
+    result.pdf = 0.0;
+    result.mis = true;
+
+    let tri = tri_buffer[light.tri];
+    let v0 = vertex_buffer[tri.x].xyz;
+    let v1 = vertex_buffer[tri.y].xyz;
+    let v2 = vertex_buffer[tri.z].xyz;
+
+    let su = sqrt(pcg_randf32(rng_seed + 1u));
+    let sv = pcg_randf32(rng_seed + 2u);
+    let point = v0 * (1.0 - su) + v1 * (su * (1.0 - sv)) + v2 * (su * sv);
+
+    let to_light = point - origin;
+    let distance_sq = dot(to_light, to_light);
+    if distance_sq < 1e-8 {
+        return result;
+    }
+    let distance = sqrt(distance_sq);
+    let direction = to_light / distance;
+    let cos_light = abs(dot(normalize(cross(v2 - v0, v1 - v0)), direction));
+    if cos_light < 1e-6 {
+        return result;
+    }
+
+    result.direction = direction;
+    result.distance = distance;
+    result.emission = material_buffer[tri.w].emission_and_roughness.xyz;
+    result.pdf = distance_sq / (light.area * cos_light);
+    return result;
+}
+
+// Point lights, and spot lights with their cone falloff.
+fn sample_point_light(light: Light, origin: vec3<f32>) -> LightSample {
+    var result: LightSample;
+    result.pdf = 0.0;
+    result.mis = false;
+
+    let to_light = light.position - origin;
+    let distance_sq = dot(to_light, to_light);
+    if distance_sq < 1e-8 {
+        return result;
+    }
+    let distance = sqrt(distance_sq);
+    result.direction = to_light / distance;
+    result.distance = distance;
+
+    var falloff = 1.0;
+    if light.kind == 2u {
+        falloff = smoothstep(light.cos_outer, light.cos_inner, dot(-result.direction, light.direction));
+    }
+    result.emission = light.emission * falloff / distance_sq;
+    result.pdf = 1.0;
+    return result;
+}
+
+// Directions are spread uniformly over the cone the light's disc covers, so
+// `emission` is the irradiance divided by that solid angle.
+fn sample_directional_light(light: Light, rng_seed: u32) -> LightSample {
+    var result: LightSample;
+    result.mis = false;
+    result.distance = INF;
+
+    let cone_solid_angle = 2.0 * PI * (1.0 - light.cos_outer);
+    if cone_solid_angle < 1e-7 {
+        result.direction = -light.direction;
+        result.emission = light.emission;
+        result.pdf = 1.0;
+        return result;
+    }
+
+    let cos_theta = 1.0 - pcg_randf32(rng_seed + 1u) * (1.0 - light.cos_outer);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * pcg_randf32(rng_seed + 2u);
+    let local = vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+    result.direction = normalize(transform_vec_to_norm_space(local, -light.direction));
+    result.emission = light.emission / cone_solid_angle;
+    result.pdf = 1.0 / cone_solid_angle;
+    return result;
+}
+
+fn sample_area_light(light: Light, origin: vec3<f32>, rng_seed: u32) -> LightSample {
+    var result: LightSample;
+    result.pdf = 0.0;
+    result.mis = false;
+
+    let point = light.position + light.edge_u * pcg_randf32(rng_seed + 1u) + light.edge_v * pcg_randf32(rng_seed + 2u);
+    let to_light = point - origin;
+    let distance_sq = dot(to_light, to_light);
+    if distance_sq < 1e-8 {
+        return result;
+    }
+    let distance = sqrt(distance_sq);
+    let direction = to_light / distance;
+    let cos_light = dot(normalize(cross(light.edge_u, light.edge_v)), -direction);
+    if cos_light < 1e-6 {
+        return result;
+    }
+
+    result.direction = direction;
+    result.distance = distance;
+    result.emission = light.emission;
+    result.pdf = distance_sq / (light.area * cos_light);
+    return result;
+}
+
+// Picks the environment map with probability `environment_prob`, otherwise an
+// entry of the light table. The returned pdf includes that choice.
+fn sample_direct(origin: vec3<f32>, rng_seed: u32, environment_prob: f32) -> LightSample {
+    if pcg_randf32(rng_seed ^ 0x1b873593u) < environment_prob {
+        var sample = sample_environment(rng_seed);
+        sample.pdf *= environment_prob;
+        return sample;
+    }
+    var sample = sample_light(origin, rng_seed);
+    sample.pdf *= 1.0 - environment_prob;
+    return sample;
+}
+
+fn environment_sample_probability() -> f32 {
+    if environment.map_info.z == 0u || environment_total_weight() <= 0.0 {
+        return 0.0;
+    }
+    if total_light_power() > 0.0 {
+        return 0.5;
+    }
+    return 1.0;
+}
+
+fn environment_total_weight() -> f32 {
+    return environment_map[arrayLength(&environment_map) - 1u].x;
+}
+
+// Scene space has -Y up, so flip into a Y-up frame before mapping to longitude and latitude.
+fn environment_uv(direction: vec3<f32>) -> vec2<f32> {
+    let d = vec3<f32>(-direction.x, -direction.y, direction.z);
+    let phi = atan2(d.x, -d.z) - environment.bottom_color_and_rotation.w;
+    let theta = acos(clamp(d.y, -1.0, 1.0));
+    return vec2<f32>(fract(0.5 + phi / (2.0 * PI)), theta / PI);
+}
+
+fn environment_direction(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI + environment.bottom_color_and_rotation.w;
+    let theta = uv.y * PI;
+    let d = vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+    return vec3<f32>(-d.x, -d.y, d.z);
+}
+
+fn environment_texel(uv: vec2<f32>) -> u32 {
+    let size = environment.map_info.xy;
+    let x = min(u32(uv.x * f32(size.x)), size.x - 1u);
+    let y = min(u32(uv.y * f32(size.y)), size.y - 1u);
+    return y * size.x + x;
+}
+
+fn environment_radiance(direction: vec3<f32>) -> vec3<f32> {
+    let intensity = environment.top_color_and_intensity.w;
+    if environment.map_info.z == 0u {
+        let top_color = environment.top_color_and_intensity.xyz;
+        let bottom_color = environment.bottom_color_and_rotation.xyz;
+        return mix(bottom_color, top_color, (1.0 + direction.y) * 0.5) * intensity;
+    }
+    return environment_map[environment_texel(environment_uv(direction))].xyz * intensity;
+}
+
+// Sampling weight of a texel, recovered from the running row sums.
+fn environment_texel_weight(texel: u32) -> f32 {
+    let x = texel % environment.map_info.x;
+    var weight = environment_map[texel].w;
+    if x > 0u {
+        weight -= environment_map[texel - 1u].w;
+    }
+    return weight;
+}
+
+// Solid angle density of `sample_environment` producing `direction`.
+fn environment_pdf(direction: vec3<f32>) -> f32 {
+    let uv = environment_uv(direction);
+    let sin_theta = sin(uv.y * PI);
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    let size = environment.map_info.xy;
+    let texel_pdf = environment_texel_weight(environment_texel(uv)) / environment_total_weight();
+    return texel_pdf * f32(size.x * size.y) / (2.0 * PI * PI * sin_theta);
+}
+
+// Picks a row from the marginal sums, a texel within it from the row's running
+// sums, then a uniform point inside the texel.
+fn sample_environment(rng_seed: u32) -> LightSample {
+    var result: LightSample;
+    result.pdf = 0.0;
+
+    let size = environment.map_info.xy;
+    let marginal_start = size.x * size.y;
+    let total = environment_total_weight();
+
+    let target_row = max(pcg_randf32(rng_seed), MIN_CDF_TARGET) * total;
+    var low = 0u;
+    var high = size.y - 1u;
+    while low < high {
+        let mid = (low + high) / 2u;
+        if environment_map[marginal_start + mid].x < target_row {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+    let y = low;
+
+    let row_start = y * size.x;
+    let target_texel = max(pcg_randf32(rng_seed + 1u), MIN_CDF_TARGET) * environment_map[row_start + size.x - 1u].w;
+    low = 0u;
+    high = size.x - 1u;
+    while low < high {
+        let mid = (low + high) / 2u;
+        if environment_map[row_start + mid].w < target_texel {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+    let x = low;
+
+    let uv = vec2<f32>(
+        (f32(x) + pcg_randf32(rng_seed + 2u)) / f32(size.x),
+        (f32(y) + pcg_randf32(rng_seed + 3u)) / f32(size.y),
+    );
+    let direction = environment_direction(uv);
+
+    result.direction = direction;
+    result.distance = INF;
+    result.mis = true;
+    result.emission = environment_radiance(direction);
+    result.pdf = environment_pdf(direction);
+    return result;
+}
+
+// Solid angle density with which `sample_light` picks a point on an emissive
+// triangle with `emission`, seen at `distance` under `cos_light`. The triangle
+// area cancels out against the power `build_light_table` gave it.
+fn light_pdf(emission: vec3<f32>, distance: f32, cos_light: f32) -> f32 {
+    let total = total_light_power();
+    if total <= 0.0 || cos_light < 1e-6 {
+        return 0.0;
+    }
+    return 2.0 * PI * luminance(emission) / total * distance * distance / cos_light;
+}
+
+// BSDF times cosine for light arriving from `direction` in xyz, and the density of
+// the matching BSDF sample in w. Not defined for glass.
+fn eval_bsdf(material: Material, in_direction: vec3<f32>, normal: vec3<f32>, direction: vec3<f32>) -> vec4<f32> {
+    let cos_theta = dot(normal, direction);
+    if cos_theta <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+
+    if material.kind == MATERIAL_DIFFUSE {
+        let pdf = cos_theta / PI;
+        return vec4<f32>(material.albedo * pdf, pdf);
+    }
+
+    if material.kind == MATERIAL_PRINCIPLED {
+        return eval_principled(material, -in_direction, normal, direction);
+    }
+
+    // Smooth metals are a delta lobe that light sampling cannot hit.
+    let alpha = ggx_alpha(material.emission_and_roughness.w);
+    let view = -in_direction;
+    let cos_view = dot(normal, view);
+    if alpha < MIN_GGX_ALPHA || cos_view <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+    let half_vector = normalize(view + direction);
+    let d = ggx_d(dot(normal, half_vector), alpha);
+    let f = metal_fresnel(material, dot(view, half_vector));
+    let value = f * d * smith_g2(cos_view, cos_theta, alpha) / (4.0 * cos_view);
+    // Density of `sample_metal`: visible normals, reflected about the half vector.
+    let pdf = smith_g1(cos_view, alpha) * d / (4.0 * cos_view);
+    return vec4<f32>(value, pdf);
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    return a / (a + b);
+}
+
 fn pcg_randu32(hash: u32) -> u32 {
     let state = hash * 747796405u + 2891336453u;
     var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
@@ -244,59 +1043,302 @@ fn rand_cosine_hemi_vec(u1: f32, u2: f32) -> vec3<f32> {
     return vec3<f32>(x, y, z);
 }
 
-fn phong_reflect(seed: u32, exponent: f32, reflect: vec3<f32>) -> vec3<f32> {
-    let phi = 2.0 * PI * pcg_randf32(seed);
-    let cos_theta = pow(pcg_randf32(seed + 1), 1.0 / (exponent + 1));
-    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
-    let phong_ray = vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
-    return transform_vec_to_norm_space(phong_ray, reflect);
+// Uniform point on the unit aperture: a disk, or with 3 or more blades a regular
+// polygon inscribed in it.
+fn sample_aperture(u1: f32, u2: f32) -> vec2<f32> {
+    let blades = u32(camera.lens.y);
+    if blades < 3u {
+        return concentric_disk(u1, u2);
+    }
+
+    // Pick one of the triangles fanning out from the center, reusing what is left
+    // of `u1` to sample within it.
+    let wedge_angle = 2.0 * PI / f32(blades);
+    let scaled = u1 * f32(blades);
+    let wedge = floor(scaled);
+    let r = sqrt(scaled - wedge);
+    let angle = camera.lens.z + wedge * wedge_angle;
+    let a = vec2<f32>(cos(angle), sin(angle));
+    let b = vec2<f32>(cos(angle + wedge_angle), sin(angle + wedge_angle));
+    return r * ((1.0 - u2) * a + u2 * b);
+}
+
+// Shirley and Chiu's area preserving map from the unit square to the unit disk.
+fn concentric_disk(u1: f32, u2: f32) -> vec2<f32> {
+    let offset = 2.0 * vec2<f32>(u1, u2) - 1.0;
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return vec2<f32>(0.0);
+    }
+    var r: f32;
+    var theta: f32;
+    if abs(offset.x) > abs(offset.y) {
+        r = offset.x;
+        theta = PI / 4.0 * (offset.y / offset.x);
+    } else {
+        r = offset.y;
+        theta = PI / 2.0 - PI / 4.0 * (offset.x / offset.y);
+    }
+    return r * vec2<f32>(cos(theta), sin(theta));
 }
 
 fn transform_vec_to_norm_space(vector: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    return normal_space_basis(normal) * vector;
+}
+
+// Columns are tangent, bitangent and normal; `v * basis` goes back to the local frame.
+fn normal_space_basis(normal: vec3<f32>) -> mat3x3<f32> {
     let up = select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(normal.y) > 0.99);
     let tangent = normalize(cross(up, normal));
     let bitangent = cross(normal, tangent);
 
-    let rot_mat = mat3x3<f32>(tangent, bitangent, normal);
-
-    return rot_mat * vector;
+    return mat3x3<f32>(tangent, bitangent, normal);
 }
 
 fn diffuse_bounce(material: Material, in_ray: Ray, normal: vec3<f32>, rng_seed: u32) -> vec3<f32> {
     return transform_vec_to_norm_space(rand_cosine_hemi_vec(pcg_randf32(rng_seed), pcg_randf32(rng_seed + 1)), normal);
 }
 
-fn metallic_bounce(material: Material, in_ray: Ray, normal: vec3<f32>, rng_seed: u32) -> vec3<f32> {
-    let reflection = reflect(in_ray.direction, normal);
-    let roughness = material.emission_and_roughness.w;
-    let exponent = pow(1.0 - roughness, 3.0) * 1000.0 + 1.0;
-    return phong_reflect(rng_seed, exponent, reflection);
+fn sample_metal(material: Material, in_direction: vec3<f32>, normal: vec3<f32>, rng_seed: u32) -> BsdfSample {
+    var result: BsdfSample;
+    let alpha = ggx_alpha(material.emission_and_roughness.w);
+    let view = -in_direction;
+    let cos_view = max(dot(normal, view), 1e-6);
+
+    if alpha < MIN_GGX_ALPHA {
+        result.direction = reflect(in_direction, normal);
+        result.weight = metal_fresnel(material, cos_view);
+        result.pdf = 0.0;
+        return result;
+    }
+
+    let basis = normal_space_basis(normal);
+    let half_vector = basis * sample_ggx_vndf(view * basis, alpha, pcg_randf32(rng_seed), pcg_randf32(rng_seed + 1));
+    result.direction = reflect(in_direction, half_vector);
+
+    let cos_light = dot(normal, result.direction);
+    if cos_light <= 0.0 {
+        result.weight = vec3<f32>(0.0);
+        result.pdf = 0.0;
+        return result;
+    }
+
+    let g1 = smith_g1(cos_view, alpha);
+    result.weight = metal_fresnel(material, dot(view, half_vector)) * smith_g2(cos_view, cos_light, alpha) / g1;
+    result.pdf = g1 * ggx_d(dot(normal, half_vector), alpha) / (4.0 * cos_view);
+    return result;
+}
+
+// Roughness is squared into the GGX alpha, as in Blender and Arnold.
+fn ggx_alpha(roughness: f32) -> f32 {
+    return roughness * roughness;
+}
+
+fn ggx_d(cos_half: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = cos_half * cos_half * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn smith_lambda(cos_theta: f32, alpha: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let tan2 = max(0.0, 1.0 - cos2) / max(cos2, 1e-8);
+    return (sqrt(1.0 + alpha * alpha * tan2) - 1.0) * 0.5;
+}
+
+fn smith_g1(cos_theta: f32, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + smith_lambda(abs(cos_theta), alpha));
+}
+
+// Height-correlated masking-shadowing.
+fn smith_g2(cos_view: f32, cos_light: f32, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + smith_lambda(abs(cos_view), alpha) + smith_lambda(abs(cos_light), alpha));
+}
+
+// Samples a microfacet normal from the normals visible from `view`, both in the
+// local frame with z along the surface normal (Dupuy and Benyoub's spherical caps).
+fn sample_ggx_vndf(view: vec3<f32>, alpha: f32, u1: f32, u2: f32) -> vec3<f32> {
+    let v = normalize(vec3<f32>(view.xy * alpha, view.z));
+    let phi = 2.0 * PI * u1;
+    let z = (1.0 - u2) * (1.0 + v.z) - v.z;
+    let sin_theta = sqrt(clamp(1.0 - z * z, 0.0, 1.0));
+    let h = vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), z) + v;
+    return normalize(vec3<f32>(h.xy * alpha, max(h.z, 1e-6)));
+}
+
+fn metal_fresnel(material: Material, cosine: f32) -> vec3<f32> {
+    if material.extinction.w > 0.0 {
+        return fresnel_conductor(cosine, material.ior.xyz, material.extinction.xyz);
+    }
+    return fresnel_schlick(material.albedo, cosine);
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cosine: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cosine, 0.0, 1.0), 5.0);
+}
+
+// Unpolarized reflectance of a conductor with complex IOR eta + ik.
+fn fresnel_conductor(cosine: f32, eta: vec3<f32>, k: vec3<f32>) -> vec3<f32> {
+    let cos2 = clamp(cosine * cosine, 0.0, 1.0);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = sqrt(t0 * t0 + 4.0 * eta * eta * k * k);
+    let a = sqrt(max(0.5 * (a2b2 + t0), vec3<f32>(0.0)));
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * sqrt(cos2) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    return 0.5 * (rs + rp);
 }
 
+// Rough glass refracts and reflects about a GGX microfacet normal instead of the
+// surface normal.
 fn transparent_material(material: Material, in_ray: Ray, normal: vec3<f32>, rng_seed: u32, t: f32, front: bool) -> GlassRefract {
     var index = select(material.ior.x, 1.0 / material.ior.x, front);
     var refraction: GlassRefract;
 
-    let cos_theta = min(dot(-in_ray.direction, normal), 1.0);
+    let alpha = ggx_alpha(material.emission_and_roughness.w);
+    var micro_normal = normal;
+    if alpha >= MIN_GGX_ALPHA {
+        let basis = normal_space_basis(normal);
+        micro_normal = basis * sample_ggx_vndf(-in_ray.direction * basis, alpha, pcg_randf32(rng_seed + 1), pcg_randf32(rng_seed + 2));
+    }
+
+    let cos_theta = clamp(dot(-in_ray.direction, micro_normal), 0.0, 1.0);
     let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
 
     let reflection_chance = schlicke(cos_theta, index);
 
-    if index * sin_theta > 1.0 || reflection_chance > pcg_randf32(rng_seed) {
-        refraction.direction = reflect(in_ray.direction, normal);
+    let reflected = index * sin_theta > 1.0 || reflection_chance > pcg_randf32(rng_seed);
+    if reflected {
+        refraction.direction = reflect(in_ray.direction, micro_normal);
         refraction.attenuation = vec3<f32>(1.0);
-        return refraction;
+    } else {
+        refraction.direction = refract(in_ray.direction, micro_normal, index);
+        if front {
+            refraction.attenuation = vec3<f32>(1.0);
+        } else {
+            refraction.attenuation = transparent_absorbed(t, material.albedo);
+        }
     }
 
-    refraction.direction = refract(in_ray.direction, normal, index);
+    if alpha >= MIN_GGX_ALPHA {
+        // Fresnel cancels against the choice between reflection and refraction,
+        // leaving the shadowing of the outgoing direction.
+        let cos_view = dot(normal, -in_ray.direction);
+        let cos_out = dot(normal, refraction.direction);
+        if (cos_out > 0.0) != reflected {
+            refraction.attenuation = vec3<f32>(0.0);
+        } else {
+            refraction.attenuation *= smith_g2(cos_view, cos_out, alpha) / smith_g1(cos_view, alpha);
+        }
+    }
 
-    if front {
-        refraction.attenuation = vec3<f32>(1.0);
+    return refraction;
+}
+
+// Principled materials follow Disney's model: a Lambertian base with sheen, a GGX
+// specular lobe that blends from a dielectric to a metal, a GGX clearcoat on top,
+// and a glass lobe replacing the dielectric part by the transmission amount.
+fn principled_transmission(material: Material) -> f32 {
+    return (1.0 - material.principled.x) * material.principled.z;
+}
+
+fn principled_f0(material: Material) -> vec3<f32> {
+    let dielectric = vec3<f32>(0.08 * material.principled.y);
+    return mix(dielectric, material.albedo, material.principled.x);
+}
+
+// Chances of sampling the diffuse, specular and clearcoat lobes, roughly in
+// proportion to how much light each reflects towards `cos_view`.
+fn principled_lobe_weights(material: Material, cos_view: f32) -> vec3<f32> {
+    let diffuse = 1.0 - material.principled.x;
+    let specular = luminance(fresnel_schlick(principled_f0(material), cos_view));
+    let clearcoat = 0.25 * material.principled.w * fresnel_schlick(vec3<f32>(0.04), cos_view).x;
+    let weights = vec3<f32>(diffuse, specular, clearcoat);
+    return weights / max(weights.x + weights.y + weights.z, 1e-6);
+}
+
+// Opaque part of the principled BSDF times cosine in xyz and the density of
+// `sample_principled` producing `direction` in w. Both are scaled by the chance
+// of not following the glass lobe, which light sampling cannot reproduce.
+fn eval_principled(material: Material, view: vec3<f32>, normal: vec3<f32>, direction: vec3<f32>) -> vec4<f32> {
+    let cos_view = dot(normal, view);
+    let cos_light = dot(normal, direction);
+    if cos_view <= 0.0 || cos_light <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+    let base_color = material.albedo;
+    let half_vector = normalize(view + direction);
+    let cos_half = dot(normal, half_vector);
+    let cos_diff = dot(direction, half_vector);
+
+    let tint = select(vec3<f32>(1.0), base_color / luminance(base_color), luminance(base_color) > 0.0);
+    let sheen = material.sheen_and_clearcoat.x * mix(vec3<f32>(1.0), tint, material.sheen_and_clearcoat.y);
+    let diffuse = (1.0 - material.principled.x) * (base_color / PI + sheen * pow(1.0 - cos_diff, 5.0)) * cos_light;
+
+    let alpha = max(ggx_alpha(material.emission_and_roughness.w), MIN_GGX_ALPHA);
+    let d = ggx_d(cos_half, alpha);
+    let specular = fresnel_schlick(principled_f0(material), cos_diff) * d * smith_g2(cos_view, cos_light, alpha) / (4.0 * cos_view);
+
+    let coat_alpha = max(ggx_alpha(material.sheen_and_clearcoat.z), MIN_GGX_ALPHA);
+    let coat_d = ggx_d(cos_half, coat_alpha);
+    let coat_f = 0.25 * material.principled.w * fresnel_schlick(vec3<f32>(0.04), cos_diff).x;
+    let clearcoat = coat_f * coat_d * smith_g2(cos_view, cos_light, coat_alpha) / (4.0 * cos_view);
+
+    let weights = principled_lobe_weights(material, cos_view);
+    let pdf = weights.x * cos_light / PI
+        + weights.y * smith_g1(cos_view, alpha) * d / (4.0 * cos_view)
+        + weights.z * smith_g1(cos_view, coat_alpha) * coat_d / (4.0 * cos_view);
+
+    let opaque = 1.0 - principled_transmission(material);
+    return vec4<f32>((diffuse + specular + clearcoat) * opaque, pdf * opaque);
+}
+
+fn sample_principled(material: Material, in_ray: Ray, normal: vec3<f32>, rng_seed: u32, t: f32, front: bool) -> BsdfSample {
+    var result: BsdfSample;
+
+    if pcg_randf32(rng_seed + 3) < principled_transmission(material) {
+        // The base color tints light entering the surface instead of being absorbed
+        // along the path inside.
+        var glass = material;
+        glass.albedo = vec3<f32>(0.0);
+        glass.kind = MATERIAL_GLASS;
+        let refraction = transparent_material(glass, in_ray, normal, rng_seed, t, front);
+        result.direction = refraction.direction;
+        result.weight = refraction.attenuation;
+        if front && dot(refraction.direction, normal) < 0.0 {
+            result.weight *= material.albedo;
+        }
+        result.pdf = 0.0;
+        return result;
+    }
+
+    let view = -in_ray.direction;
+    let weights = principled_lobe_weights(material, dot(normal, view));
+    let lobe = pcg_randf32(rng_seed + 4);
+    let u1 = pcg_randf32(rng_seed);
+    let u2 = pcg_randf32(rng_seed + 1);
+    if lobe < weights.x {
+        result.direction = transform_vec_to_norm_space(rand_cosine_hemi_vec(u1, u2), normal);
     } else {
-        refraction.attenuation = transparent_absorbed(t, material.albedo_and_mat.xyz);
+        let roughness = select(material.emission_and_roughness.w, material.sheen_and_clearcoat.z, lobe >= weights.x + weights.y);
+        let alpha = max(ggx_alpha(roughness), MIN_GGX_ALPHA);
+        let basis = normal_space_basis(normal);
+        let half_vector = basis * sample_ggx_vndf(view * basis, alpha, u1, u2);
+        result.direction = reflect(in_ray.direction, half_vector);
     }
 
-    return refraction;
+    let bsdf = eval_principled(material, view, normal, result.direction);
+    if bsdf.w <= 0.0 {
+        result.weight = vec3<f32>(0.0);
+        result.pdf = 0.0;
+        return result;
+    }
+    result.weight = bsdf.xyz / bsdf.w;
+    result.pdf = bsdf.w;
+    return result;
 }
 
 fn transparent_absorbed(distance: f32, absorbtion: vec3<f32>) -> vec3<f32> {
@@ -16,14 +16,87 @@ fn vs_main(
     return out;
 }
 
+const TONEMAP_CLAMP: u32 = 0u;
+const TONEMAP_REINHARD: u32 = 1u;
+const TONEMAP_ACES: u32 = 2u;
+const TONEMAP_AGX: u32 = 3u;
+
+struct Display {
+    // rgb: exposure times the white balance gains
+    scale: vec4<f32>,
+    // x: tonemapping operator
+    tonemap: vec4<u32>,
+};
+
 @group(0) @binding(0)
 var tex: texture_2d<f32>;
 @group(0) @binding(1)
 var samp: sampler;
+@group(0) @binding(2)
+var<uniform> display: Display;
 
+// The traced image is linear HDR radiance; the surface is sRGB, so this outputs
+// linear values in 0..=1. Mirrors `DisplaySettings::apply`.
 @fragment
 fn fs_main(
     @location(0) uv: vec2<f32>
 ) -> @location(0) vec4<f32> {
-    return textureSample(tex, samp, uv);
+    let color = textureSample(tex, samp, uv).rgb * display.scale.rgb;
+    var mapped: vec3<f32>;
+    switch display.tonemap.x {
+        case TONEMAP_REINHARD: {
+            mapped = color / (1.0 + color);
+        }
+        case TONEMAP_ACES: {
+            mapped = aces_fitted(color);
+        }
+        case TONEMAP_AGX: {
+            mapped = agx(color);
+        }
+        default: {
+            mapped = color;
+        }
+    }
+    return vec4<f32>(clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms.
+fn aces_fitted(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777,
+    );
+    let output = mat3x3<f32>(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602,
+    );
+    let v = input * color;
+    let fitted = (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081);
+    return output * fitted;
+}
+
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+// AgX base look with the polynomial fit of its contrast curve, decoded from the
+// curve's display encoding with a 2.2 gamma.
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.84247906, 0.04232824, 0.04237565,
+        0.0784336, 0.87846864, 0.0784336,
+        0.07922375, 0.07916613, 0.879143,
+    );
+    let outset = mat3x3<f32>(
+        1.196879, -0.05289685, -0.05297164,
+        -0.09802088, 1.1519031, -0.09804345,
+        -0.09902974, -0.09896118, 1.1510737,
+    );
+    let log_color = clamp(log2(max(inset * color, vec3<f32>(1e-10))), vec3<f32>(AGX_MIN_EV), vec3<f32>(AGX_MAX_EV));
+    let x = (log_color - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let encoded = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    return pow(max(outset * encoded, vec3<f32>(0.0)), vec3<f32>(2.2));
 }
\ No newline at end of file